@@ -4,6 +4,20 @@ mod m20240615_151855_create_users_table;
 mod m20240615_151858_create_photos_table;
 mod m20240615_153438_create_ban_table;
 mod m20250401_221940_create_reactions_table;
+mod m20261018_120000_add_photos_queued_at;
+mod m20261018_121000_create_submissions_table;
+mod m20261018_122000_create_moderators_table;
+mod m20261018_123000_create_moderation_events_table;
+mod m20261018_124000_add_photos_status;
+mod m20261018_125000_add_ban_expires_at;
+mod m20261018_125500_add_moderation_events_user_id;
+mod m20261018_126000_add_users_limits;
+mod m20261018_127000_add_photos_phash;
+mod m20261018_128000_add_photos_caption;
+mod m20261018_129000_add_photos_caption_override;
+mod m20261018_130000_add_photos_location;
+mod m20261018_131000_add_photos_private_metadata;
+mod m20261018_132000_add_photos_watermark;
 
 pub struct Migrator;
 
@@ -15,6 +29,20 @@ impl MigratorTrait for Migrator {
             Box::new(m20240615_151858_create_photos_table::Migration),
             Box::new(m20240615_153438_create_ban_table::Migration),
             Box::new(m20250401_221940_create_reactions_table::Migration),
+            Box::new(m20261018_120000_add_photos_queued_at::Migration),
+            Box::new(m20261018_121000_create_submissions_table::Migration),
+            Box::new(m20261018_122000_create_moderators_table::Migration),
+            Box::new(m20261018_123000_create_moderation_events_table::Migration),
+            Box::new(m20261018_124000_add_photos_status::Migration),
+            Box::new(m20261018_125000_add_ban_expires_at::Migration),
+            Box::new(m20261018_125500_add_moderation_events_user_id::Migration),
+            Box::new(m20261018_126000_add_users_limits::Migration),
+            Box::new(m20261018_127000_add_photos_phash::Migration),
+            Box::new(m20261018_128000_add_photos_caption::Migration),
+            Box::new(m20261018_129000_add_photos_caption_override::Migration),
+            Box::new(m20261018_130000_add_photos_location::Migration),
+            Box::new(m20261018_131000_add_photos_private_metadata::Migration),
+            Box::new(m20261018_132000_add_photos_watermark::Migration),
         ]
     }
 }
@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .add_column_if_not_exists(timestamp_null(Photos::QueuedAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Photos::Table).drop_column(Photos::QueuedAt).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    QueuedAt,
+}
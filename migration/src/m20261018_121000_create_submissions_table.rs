@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Submissions::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Submissions::Uuid).default(Expr::cust("gen_random_uuid()")))
+                    .col(big_integer(Submissions::UserId))
+                    .col(string_uniq(Submissions::MediaGroupId))
+                    .col(big_integer_null(Submissions::MsgId))
+                    .col(timestamp(Submissions::CreatedAt).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("submissions_user_id_users_user_id_fkey")
+                    .from(Submissions::Table, Submissions::UserId)
+                    .to(Users::Table, Users::UserId)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .add_column_if_not_exists(uuid_null(Photos::SubmissionUuid))
+                    .add_column_if_not_exists(integer_null(Photos::Position))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("photos_submission_uuid_submissions_uuid_fkey")
+                    .from(Photos::Table, Photos::SubmissionUuid)
+                    .to(Submissions::Table, Submissions::Uuid)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .drop_column(Photos::SubmissionUuid)
+                    .drop_column(Photos::Position)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table(Table::drop().if_exists().table(Submissions::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Submissions {
+    Table,
+    Uuid,
+    UserId,
+    MediaGroupId,
+    MsgId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    SubmissionUuid,
+    Position,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    UserId,
+}
@@ -0,0 +1,129 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(ModeratorRole::Enum)
+                    .values([ModeratorRole::Owner, ModeratorRole::Moderator, ModeratorRole::Viewer])
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(Moderators::Table)
+                    .if_not_exists()
+                    .col(big_integer(Moderators::UserId).primary_key())
+                    .col(ColumnDef::new(Moderators::Role).custom(Alias::new("\"ModeratorRole\"")).not_null())
+                    .col(big_integer_null(Moderators::AddedBy))
+                    .col(timestamp(Moderators::CreatedAt).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModerationCards::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(ModerationCards::Uuid).default(Expr::cust("gen_random_uuid()")))
+                    .col(uuid_null(ModerationCards::PhotoUuid))
+                    .col(uuid_null(ModerationCards::SubmissionUuid))
+                    .col(big_integer(ModerationCards::ChatId))
+                    .col(big_integer(ModerationCards::MsgId))
+                    .col(timestamp(ModerationCards::CreatedAt).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("moderation_cards_chat_msg_idx")
+                    .table(ModerationCards::Table)
+                    .col(ModerationCards::ChatId)
+                    .col(ModerationCards::MsgId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("moderation_cards_photo_uuid_fkey")
+                    .from(ModerationCards::Table, ModerationCards::PhotoUuid)
+                    .to(Photos::Table, Photos::Uuid)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("moderation_cards_submission_uuid_fkey")
+                    .from(ModerationCards::Table, ModerationCards::SubmissionUuid)
+                    .to(Submissions::Table, Submissions::Uuid)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().if_exists().table(ModerationCards::Table).to_owned())
+            .await?;
+        manager.drop_table(Table::drop().if_exists().table(Moderators::Table).to_owned()).await?;
+        manager.drop_type(Type::drop().if_exists().name(ModeratorRole::Enum).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Moderators {
+    Table,
+    UserId,
+    Role,
+    AddedBy,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ModerationCards {
+    Table,
+    Uuid,
+    PhotoUuid,
+    SubmissionUuid,
+    ChatId,
+    MsgId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    Uuid,
+}
+
+#[derive(DeriveIden)]
+enum Submissions {
+    Table,
+    Uuid,
+}
+
+#[derive(DeriveIden)]
+pub enum ModeratorRole {
+    #[sea_orm(iden = "ModeratorRole")]
+    Enum,
+    #[sea_orm(iden = "Owner")]
+    Owner,
+    #[sea_orm(iden = "Moderator")]
+    Moderator,
+    #[sea_orm(iden = "Viewer")]
+    Viewer,
+}
@@ -0,0 +1,103 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(ModerationAction::Enum)
+                    .values([
+                        ModerationAction::Approve,
+                        ModerationAction::Decline,
+                        ModerationAction::Ban,
+                        ModerationAction::Unban,
+                        ModerationAction::Requeue,
+                    ])
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModerationEvents::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(ModerationEvents::Uuid).default(Expr::cust("gen_random_uuid()")))
+                    .col(uuid_null(ModerationEvents::PhotoUuid))
+                    .col(big_integer(ModerationEvents::ModeratorId))
+                    .col(
+                        ColumnDef::new(ModerationEvents::Action)
+                            .custom(Alias::new("\"ModerationAction\""))
+                            .not_null(),
+                    )
+                    .col(text_null(ModerationEvents::Reason))
+                    .col(timestamp(ModerationEvents::CreatedAt).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("moderation_events_photo_uuid_idx")
+                    .table(ModerationEvents::Table)
+                    .col(ModerationEvents::PhotoUuid)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("moderation_events_photo_uuid_fkey")
+                    .from(ModerationEvents::Table, ModerationEvents::PhotoUuid)
+                    .to(Photos::Table, Photos::Uuid)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().if_exists().table(ModerationEvents::Table).to_owned())
+            .await?;
+        manager.drop_type(Type::drop().if_exists().name(ModerationAction::Enum).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ModerationEvents {
+    Table,
+    Uuid,
+    PhotoUuid,
+    ModeratorId,
+    Action,
+    Reason,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    Uuid,
+}
+
+#[derive(DeriveIden)]
+pub enum ModerationAction {
+    #[sea_orm(iden = "ModerationAction")]
+    Enum,
+    #[sea_orm(iden = "Approve")]
+    Approve,
+    #[sea_orm(iden = "Decline")]
+    Decline,
+    #[sea_orm(iden = "Ban")]
+    Ban,
+    #[sea_orm(iden = "Unban")]
+    Unban,
+    #[sea_orm(iden = "Requeue")]
+    Requeue,
+}
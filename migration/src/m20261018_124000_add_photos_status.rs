@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::{sea_query::extension::postgres::Type, *};
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_type(
+                Type::create()
+                    .as_enum(PhotoStatus::Enum)
+                    .values([
+                        PhotoStatus::Pending,
+                        PhotoStatus::Approved,
+                        PhotoStatus::Declined,
+                        PhotoStatus::Withdrawn,
+                        PhotoStatus::Failed,
+                    ])
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Photos::Status)
+                            .custom(Alias::new("\"PhotoStatus\""))
+                            .not_null()
+                            .default(Expr::cust("'Pending'")),
+                    )
+                    .add_column_if_not_exists(text_null(Photos::DeclineReason))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Photos waiting in the publishing queue were approved by a moderator as well
+        manager
+            .get_connection()
+            .execute_unprepared(r#"UPDATE "photos" SET "status" = 'Approved' WHERE "is_approved" OR "queued_at" IS NOT NULL"#)
+            .await?;
+
+        manager
+            .alter_table(Table::alter().table(Photos::Table).drop_column(Photos::IsApproved).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .add_column_if_not_exists(boolean(Photos::IsApproved).default(false))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(r#"UPDATE "photos" SET "is_approved" = "channel_msg_id" IS NOT NULL"#)
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .drop_column(Photos::Status)
+                    .drop_column(Photos::DeclineReason)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_type(Type::drop().if_exists().name(PhotoStatus::Enum).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    IsApproved,
+    Status,
+    DeclineReason,
+}
+
+#[derive(DeriveIden)]
+pub enum PhotoStatus {
+    #[sea_orm(iden = "PhotoStatus")]
+    Enum,
+    #[sea_orm(iden = "Pending")]
+    Pending,
+    #[sea_orm(iden = "Approved")]
+    Approved,
+    #[sea_orm(iden = "Declined")]
+    Declined,
+    #[sea_orm(iden = "Withdrawn")]
+    Withdrawn,
+    #[sea_orm(iden = "Failed")]
+    Failed,
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Ban::Table)
+                    .add_column_if_not_exists(timestamp_null(Ban::ExpiresAt))
+                    .add_column_if_not_exists(big_integer_null(Ban::BannedBy))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("ban_user_id_idx")
+                    .table(Ban::Table)
+                    .col(Ban::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().if_exists().name("ban_user_id_idx").table(Ban::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Ban::Table)
+                    .drop_column(Ban::ExpiresAt)
+                    .drop_column(Ban::BannedBy)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Ban {
+    Table,
+    UserId,
+    ExpiresAt,
+    BannedBy,
+}
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::schema::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .add_column_if_not_exists(double_null(Photos::Latitude))
+                    .add_column_if_not_exists(double_null(Photos::Longitude))
+                    .add_column_if_not_exists(boolean(Photos::PublishLocation).default(false))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Photos::Table)
+                    .drop_column(Photos::Latitude)
+                    .drop_column(Photos::Longitude)
+                    .drop_column(Photos::PublishLocation)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Photos {
+    Table,
+    Latitude,
+    Longitude,
+    PublishLocation,
+}
@@ -1,23 +1,32 @@
 use crate::bot::{
-    Bot,
+    Bot, BotManager,
     traits::DialogueContext,
     types::{CallbackData, CallbackOperation},
 };
-use crate::db::entity::{photos, prelude::Photos};
-use crate::redis::{RedisManager, types::QueueMessage};
+use crate::db::entity::{
+    moderation_events::ModerationAction,
+    photos,
+    prelude::{Ban, ModerationEvents, Moderators, Photos, Submissions},
+    submissions,
+};
+use crate::redis::RedisManager;
+use crate::types::CanMention;
 use anyhow::Result;
-use serde_json::json;
 use teloxide::{
-    dispatching::{
-        UpdateHandler,
-        dialogue::{GetChatId, RedisStorage, serializer::Json},
-    },
+    dispatching::{UpdateHandler, dialogue::GetChatId},
     prelude::*,
+    types::{InlineKeyboardButtonKind, InlineKeyboardMarkup},
 };
+use uuid::Uuid;
 
 use super::{
     BotDialogue, GlobalState,
-    dialogue::{decline_photo::State, types::DeclinePhoto},
+    dialogue::{
+        ban_user,
+        decline_photo::State,
+        edit_caption,
+        types::{BanUser, DeclinePhoto, EditCaption},
+    },
 };
 
 pub struct CallbackHandler {
@@ -48,31 +57,78 @@ impl CallbackHandler {
             }
         };
 
-        let photo;
+        // These are pressed by authors of the photos, so they go before the moderators check
+        match (&data.operation, data.document) {
+            (CallbackOperation::Withdraw, Some(doc)) => return handler.withdraw(doc).await,
+            (CallbackOperation::ToggleLocation, Some(doc)) => return handler.toggle_location(doc).await,
+            _ => {}
+        }
 
-        if let Some(doc) = &data.document {
-            photo = match Photos::get_by_id(*doc).await {
-                Some(ph) => ph,
+        if !Moderators::can_moderate(handler.callback.from.id.0 as i64).await {
+            handler
+                .bot
+                .answer_callback_query(handler.callback.id.clone())
+                .text(t!("messages.not_enough_rights"))
+                .await?;
+
+            return Ok(());
+        }
+
+        match (&data.operation, data.page, data.user) {
+            (CallbackOperation::BansPage, Some(page), _) => return handler.bans_page(page).await,
+            (CallbackOperation::Unban, _, Some(user_id)) => return handler.unban(user_id).await,
+            _ => {}
+        }
+
+        let doc = match data.document {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        if let CallbackOperation::ApproveAlbum | CallbackOperation::DeclineAlbum = data.operation {
+            let submission = match Submissions::get_by_id(doc).await {
+                Some(s) => s,
                 None => {
-                    error!("No photo found");
+                    error!("No submission found");
 
                     return Ok(());
                 }
             };
-        } else {
-            return Ok(());
+
+            return match data.operation {
+                CallbackOperation::ApproveAlbum => handler.approve_album(&submission).await,
+                _ => handler.decline_album(&submission).await,
+            };
         }
 
+        let photo = match Photos::get_by_id(doc).await {
+            Some(ph) => ph,
+            None => {
+                error!("No photo found");
+
+                return Ok(());
+            }
+        };
+
         match data.operation {
             CallbackOperation::Approve => {
                 handler.approve(&photo).await?;
-
-                let msg = handler.callback.message.unwrap();
-                handler.bot.delete_message(msg.chat().id, msg.id()).await?;
             }
             CallbackOperation::Decline => {
                 handler.decline(&photo).await?;
             }
+            CallbackOperation::TogglePhoto => {
+                handler.toggle_photo(&photo).await?;
+            }
+            CallbackOperation::BanAuthor => {
+                handler.ban_author(&photo).await?;
+            }
+            CallbackOperation::EditCaption => {
+                handler.edit_caption(&photo).await?;
+            }
+            CallbackOperation::ToggleWatermark => {
+                handler.toggle_watermark(&photo).await?;
+            }
             _ => {}
         };
 
@@ -80,19 +136,39 @@ impl CallbackHandler {
     }
 
     async fn approve(&self, photo_doc: &photos::Model) -> Result<()> {
-        let redis = RedisManager::global();
+        if !photo_doc.approve().await {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
 
-        redis.add_queue_item(&json!(QueueMessage::approve(photo_doc.uuid))).await;
+        ModerationEvents::add(Some(photo_doc.uuid), self.callback.from.id.0 as i64, ModerationAction::Approve, None).await;
 
         self.bot
             .answer_callback_query(self.callback.id.clone())
-            .text("Отправил в очередь на постинг")
+            .text(t!("messages.photo_queued", count = Photos::count_ready().await))
             .await?;
 
+        BotManager::global()
+            .resolve_photo_cards(photo_doc, &t!("messages.card_approved", moderator = self.callback.from.mention_or_url()))
+            .await;
+
         Ok(())
     }
 
     async fn decline(&self, photo_doc: &photos::Model) -> Result<()> {
+        if !photo_doc.is_pending() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+
         let cmd_user = self.callback.from.id.0 as i64;
         let state = DeclinePhoto {
             photo_id: photo_doc.uuid,
@@ -112,12 +188,410 @@ impl CallbackHandler {
 
         Ok(())
     }
+
+    async fn withdraw(&self, doc: Uuid) -> Result<()> {
+        let photo = match Photos::get_by_id(doc).await {
+            Some(p) if p.user_id == self.callback.from.id.0 as i64 => p,
+            _ => {
+                self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+                return Ok(());
+            }
+        };
+
+        let submission = photo.submission().await;
+        let photos = match &submission {
+            Some(s) => s.photos().await.into_iter().filter(|p| p.is_pending()).collect(),
+            None => vec![photo],
+        };
+
+        let mut withdrawn = Vec::with_capacity(photos.len());
+
+        for photo in photos {
+            if photo.withdraw().await {
+                withdrawn.push(photo);
+            }
+        }
+
+        if withdrawn.is_empty() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.withdraw_too_late"))
+                .await?;
+
+            return Ok(());
+        }
+
+        self.bot
+            .answer_callback_query(self.callback.id.clone())
+            .text(t!("messages.photo_withdrawn", count = withdrawn.len()))
+            .await?;
+
+        let manager = BotManager::global();
+        let decision = t!("messages.card_withdrawn");
+
+        for photo in &withdrawn {
+            manager.resolve_photo_cards(photo, &decision).await;
+        }
+
+        if let Some(s) = &submission {
+            manager.resolve_submission_cards(s, &decision).await;
+        }
+
+        if let Some(msg) = self.callback.regular_message()
+            && let Some(markup) = msg.reply_markup()
+        {
+            let uuids: Vec<Uuid> = withdrawn.iter().map(|p| p.uuid).collect();
+
+            self.bot
+                .edit_message_reply_markup(msg.chat.id, msg.id)
+                .reply_markup(without_withdrawn(markup, &uuids))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn toggle_location(&self, doc: Uuid) -> Result<()> {
+        let photo = match Photos::get_by_id(doc).await {
+            Some(p) if p.user_id == self.callback.from.id.0 as i64 => p,
+            _ => {
+                self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+                return Ok(());
+            }
+        };
+
+        let photos = match photo.submission().await {
+            Some(s) => s.photos().await,
+            None => vec![photo.clone()],
+        };
+
+        if photos.iter().any(|p| p.is_posted()) {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+
+        let publish = !photo.publish_location;
+
+        for p in &photos {
+            p.set_publish_location(publish).await;
+        }
+
+        let text = if publish {
+            t!("messages.location_will_be_published")
+        } else {
+            t!("messages.location_will_be_hidden")
+        };
+
+        self.bot.answer_callback_query(self.callback.id.clone()).text(text).await?;
+
+        if let Some(msg) = self.callback.regular_message() {
+            let has_location = photos.iter().any(|p| p.location().is_some());
+            let photo = photos::Model {
+                publish_location: publish,
+                ..photo
+            };
+
+            self.bot
+                .edit_message_reply_markup(msg.chat.id, msg.id)
+                .reply_markup(super::markups::get_thanks_markup(&photo, has_location))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn toggle_watermark(&self, photo_doc: &photos::Model) -> Result<()> {
+        if !photo_doc.is_editable() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+
+        let watermark = !photo_doc.watermark;
+
+        if !photo_doc.set_watermark(watermark).await {
+            error!("Can't toggle watermark of photo {}", photo_doc.uuid);
+            self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+            return Ok(());
+        }
+
+        let text = if watermark {
+            t!("messages.watermark_enabled")
+        } else {
+            t!("messages.watermark_disabled")
+        };
+
+        self.bot.answer_callback_query(self.callback.id.clone()).text(text).await?;
+
+        // Every moderation chat has its own card, all of them have to show the new state
+        BotManager::global()
+            .refresh_photo_cards(&photos::Model {
+                watermark,
+                ..photo_doc.clone()
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn edit_caption(&self, photo_doc: &photos::Model) -> Result<()> {
+        if !photo_doc.is_editable() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+
+        self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+        let cmd_user = self.callback.from.id.0 as i64;
+        let state = EditCaption {
+            photo_id: photo_doc.uuid,
+            ..Default::default()
+        };
+
+        if state.set(cmd_user).await {
+            self.dialogue.update(GlobalState::EditCaption(edit_caption::State::Caption)).await?;
+
+            let preview = BotManager::global().get_post_caption_preview(photo_doc).await;
+
+            self.bot
+                .send_message(
+                    self.callback.chat_id().unwrap(),
+                    t!("messages.enter_caption", preview = preview, max = photos::MAX_CAPTION_LENGTH),
+                )
+                .reply_markup(super::markups::get_cancel_markup())
+                .await?;
+        };
+
+        Ok(())
+    }
+
+    async fn ban_author(&self, photo_doc: &photos::Model) -> Result<()> {
+        if Ban::exists(photo_doc.user_id).await {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.user_already_banned"))
+                .await?;
+
+            return Ok(());
+        }
+
+        let cmd_user = self.callback.from.id.0 as i64;
+        let state = BanUser {
+            user_id: photo_doc.user_id,
+            photo_id: Some(photo_doc.uuid),
+            ..Default::default()
+        };
+
+        if state.set(cmd_user).await {
+            self.dialogue.update(GlobalState::BanUser(ban_user::State::Reason)).await?;
+
+            self.bot
+                .send_message(self.callback.chat_id().unwrap(), t!("messages.enter_ban_reason"))
+                .reply_markup(super::markups::get_cancel_markup())
+                .await?;
+        };
+
+        self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+        Ok(())
+    }
+
+    async fn toggle_photo(&self, photo_doc: &photos::Model) -> Result<()> {
+        let submission = match photo_doc.submission().await {
+            Some(s) => s,
+            None => {
+                error!("Photo {} is not a part of album", photo_doc.uuid);
+
+                return Ok(());
+            }
+        };
+
+        let mut excluded = get_excluded(&submission).await;
+
+        if let Some(pos) = excluded.iter().position(|u| *u == photo_doc.uuid) {
+            excluded.remove(pos);
+        } else {
+            excluded.push(photo_doc.uuid);
+        }
+
+        RedisManager::global().set_model(&excluded_key(&submission), &excluded).await;
+
+        self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+        BotManager::global().refresh_submission_cards(&submission).await;
+
+        Ok(())
+    }
+
+    async fn approve_album(&self, submission: &submissions::Model) -> Result<()> {
+        let excluded = get_excluded(submission).await;
+        let photos = get_pending(submission).await;
+
+        if photos.is_empty() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+
+        if photos.iter().all(|p| excluded.contains(&p.uuid)) {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.album_nothing_selected"))
+                .await?;
+
+            return Ok(());
+        }
+
+        let moderator_id = self.callback.from.id.0 as i64;
+
+        for photo in &photos {
+            if excluded.contains(&photo.uuid) {
+                if photo.decline(None).await {
+                    ModerationEvents::add(Some(photo.uuid), moderator_id, ModerationAction::Decline, None).await;
+                }
+            } else if photo.approve().await {
+                ModerationEvents::add(Some(photo.uuid), moderator_id, ModerationAction::Approve, None).await;
+            } else {
+                error!("Can't approve photo {}", photo.uuid);
+            }
+        }
+
+        RedisManager::global().delete_by_key(&excluded_key(submission)).await;
+
+        self.bot
+            .answer_callback_query(self.callback.id.clone())
+            .text(t!("messages.photo_queued", count = Photos::count_ready().await))
+            .await?;
+
+        BotManager::global()
+            .resolve_submission_cards(submission, &t!("messages.card_approved", moderator = self.callback.from.mention_or_url()))
+            .await;
+
+        Ok(())
+    }
+
+    async fn decline_album(&self, submission: &submissions::Model) -> Result<()> {
+        let cmd_user = self.callback.from.id.0 as i64;
+        let photos = get_pending(submission).await;
+
+        if photos.is_empty() {
+            self.bot
+                .answer_callback_query(self.callback.id.clone())
+                .text(t!("messages.photo_already_processed"))
+                .await?;
+
+            return Ok(());
+        }
+        let first = match photos.first() {
+            Some(p) => p,
+            None => {
+                error!("Submission {} has no photos", submission.uuid);
+
+                return Ok(());
+            }
+        };
+
+        let state = DeclinePhoto {
+            photo_id: first.uuid,
+            submission_id: Some(submission.uuid),
+            ..Default::default()
+        };
+
+        if state.set(cmd_user).await {
+            self.dialogue.update(GlobalState::DeclinePhoto(State::Reason)).await?;
+
+            self.bot
+                .send_message(self.callback.chat_id().unwrap(), t!("messages.enter_decline_reason"))
+                .reply_markup(super::markups::get_cancel_markup())
+                .await?;
+        };
+
+        self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+        Ok(())
+    }
+
+    async fn bans_page(&self, page: u64) -> Result<()> {
+        self.bot.answer_callback_query(self.callback.id.clone()).await?;
+
+        self.show_bans(page).await
+    }
+
+    async fn show_bans(&self, page: u64) -> Result<()> {
+        let (text, markup) = super::command::render_bans(page).await;
+
+        if let Some(msg) = &self.callback.message {
+            self.bot.edit_message_text(msg.chat().id, msg.id(), text).reply_markup(markup).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn unban(&self, user_id: i64) -> Result<()> {
+        let text = if super::command::unban_user(user_id, self.callback.from.id.0 as i64).await {
+            t!("messages.user_unbanned", user_id = user_id)
+        } else {
+            t!("messages.ban_not_found")
+        };
+
+        self.bot.answer_callback_query(self.callback.id.clone()).text(text).await?;
+
+        self.show_bans(0).await
+    }
+}
+
+/// Drops withdraw buttons of the photos which are already withdrawn
+fn without_withdrawn(markup: &InlineKeyboardMarkup, uuids: &[Uuid]) -> InlineKeyboardMarkup {
+    let rows = markup.inline_keyboard.iter().map(|row| {
+        row.iter()
+            .filter(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => serde_json::from_str::<CallbackData>(data)
+                    .ok()
+                    .and_then(|d| d.document)
+                    .is_none_or(|doc| !uuids.contains(&doc)),
+                _ => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+
+    InlineKeyboardMarkup::new(rows.filter(|row| !row.is_empty()))
+}
+
+fn excluded_key(submission: &submissions::Model) -> String {
+    format!("album_excluded_{}", submission.uuid)
+}
+
+/// Photos of the album card, the ones rejected on inspection never get there
+pub(super) async fn get_pending(submission: &submissions::Model) -> Vec<photos::Model> {
+    submission.photos().await.into_iter().filter(|p| p.is_pending()).collect()
+}
+
+pub(super) async fn get_excluded(submission: &submissions::Model) -> Vec<Uuid> {
+    RedisManager::global().get_model(&excluded_key(submission)).await.unwrap_or_default()
 }
 
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
     dptree::entry().branch(
         Update::filter_callback_query()
-            .enter_dialogue::<CallbackQuery, RedisStorage<Json>, GlobalState>()
+            .chain(super::dialogue::enter())
             .branch(dptree::case![GlobalState::Idle].endpoint(CallbackHandler::handle)),
     )
 }
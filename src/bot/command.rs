@@ -1,21 +1,25 @@
 use crate::Application;
 use crate::bot::{Bot, BotManager};
-use crate::db::entity::prelude::{Ban, Photos};
-use std::sync::Arc;
-use teloxide::{
-    dispatching::{
-        UpdateHandler,
-        dialogue::{RedisStorage, serializer::Json},
-    },
-    macros::BotCommands,
-    prelude::*,
+use crate::db::entity::{
+    moderation_events::ModerationAction,
+    moderators::ModeratorRole,
+    photos::{self, PhotoStatus},
+    prelude::{Ban, ModerationCards, ModerationEvents, Moderators, Photos, Submissions, Users},
 };
+use crate::types::CanMention;
+use chrono::Duration;
+use std::sync::Arc;
+use teloxide::{dispatching::UpdateHandler, macros::BotCommands, prelude::*, types::InlineKeyboardMarkup};
+use uuid::Uuid;
 
 use super::dialogue::ban_user::State;
 use super::dialogue::types::BanUser;
 use super::traits::DialogueContext;
 use super::{BotDialogue, GlobalState};
 
+const BANS_PER_PAGE: u64 = 10;
+const MY_PHOTOS_LIMIT: u64 = 20;
+
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase", description = "Команды которые поддерживает бот:")]
 pub enum BotCommand {
@@ -23,8 +27,28 @@ pub enum BotCommand {
     Help,
     #[command(description = "Старт")]
     Start,
+    #[command(description = "Мои фото")]
+    My,
     #[command(description = "Забанить", hide)]
-    Ban,
+    Ban(String),
+    #[command(description = "Разбанить", hide)]
+    Unban(String),
+    #[command(description = "Список банов", hide)]
+    Bans,
+    #[command(description = "Изменить подпись фото", hide)]
+    Caption(String),
+    #[command(description = "Лимиты пользователя", hide)]
+    Limits(String),
+    #[command(description = "Добавить модератора", hide)]
+    AddMod(String),
+    #[command(description = "Удалить модератора", hide)]
+    DelMod(String),
+    #[command(description = "Список модераторов", hide)]
+    Mods,
+    #[command(description = "История модерации фото", hide)]
+    History(String),
+    #[command(description = "Повторить публикацию фото", hide)]
+    Requeue(String),
 }
 
 pub struct CommandHandler {
@@ -38,11 +62,17 @@ impl CommandHandler {
     pub async fn handle(bot: Bot, msg: Message, cmd: BotCommand, app: Arc<Application>, dialogue: BotDialogue) -> anyhow::Result<()> {
         let handler = Self { app, bot, msg, dialogue };
 
-        if !handler.msg.chat.is_private() {
+        let user_id = match &handler.msg.from {
+            Some(u) => u.id.0 as i64,
+            None => return Ok(()),
+        };
+
+        // In the moderators chat only moderation commands are handled, the rest are about the sender's own photos
+        if !handler.msg.chat.is_private() && matches!(cmd, BotCommand::Help | BotCommand::Start | BotCommand::My) {
             return Ok(());
         }
 
-        if Ban::exists(handler.msg.chat.id.0).await {
+        if Ban::exists(user_id).await {
             return Ok(());
         }
 
@@ -53,8 +83,38 @@ impl CommandHandler {
             BotCommand::Start => {
                 handler.start().await?;
             }
-            BotCommand::Ban => {
-                handler.ban().await?;
+            BotCommand::My => {
+                handler.my().await?;
+            }
+            BotCommand::Ban(args) => {
+                handler.ban(&args).await?;
+            }
+            BotCommand::Unban(args) => {
+                handler.unban(&args).await?;
+            }
+            BotCommand::Bans => {
+                handler.bans().await?;
+            }
+            BotCommand::Caption(args) => {
+                handler.caption(&args).await?;
+            }
+            BotCommand::Limits(args) => {
+                handler.limits(&args).await?;
+            }
+            BotCommand::AddMod(args) => {
+                handler.add_moderator(&args).await?;
+            }
+            BotCommand::DelMod(args) => {
+                handler.remove_moderator(&args).await?;
+            }
+            BotCommand::Mods => {
+                handler.moderators().await?;
+            }
+            BotCommand::History(args) => {
+                handler.history(&args).await?;
+            }
+            BotCommand::Requeue(args) => {
+                handler.requeue(&args).await?;
             }
         };
 
@@ -75,43 +135,528 @@ impl CommandHandler {
         Ok(())
     }
 
-    async fn ban(&self) -> anyhow::Result<()> {
+    async fn my(&self) -> anyhow::Result<()> {
+        let photos = Photos::get_by_user(self.msg.chat.id.0, MY_PHOTOS_LIMIT).await;
+
+        if photos.is_empty() {
+            self.bot.send_message(self.msg.chat.id, t!("messages.my_empty")).await?;
+
+            return Ok(());
+        }
+
         let manager = BotManager::global();
+        let mut lines = vec![t!("messages.my_title", count = photos.len()).to_string()];
+
+        for (i, photo) in photos.iter().enumerate() {
+            let date = photo.created_at.map(|d| d.format("%d.%m.%Y").to_string()).unwrap_or_default();
+            let status = match (photo.status, photo.channel_msg_id) {
+                (_, Some(msg_id)) => t!("messages.my_status_posted", url = manager.get_post_url(msg_id)),
+                (PhotoStatus::Pending, _) => t!("messages.my_status_pending"),
+                (PhotoStatus::Approved, _) => t!("messages.my_status_approved"),
+                (PhotoStatus::Declined, _) => match &photo.decline_reason {
+                    Some(reason) => t!("messages.my_status_declined_by_reason", reason = reason),
+                    None => t!("messages.my_status_declined"),
+                },
+                (PhotoStatus::Withdrawn, _) => t!("messages.my_status_withdrawn"),
+                (PhotoStatus::Failed, _) => t!("messages.my_status_failed"),
+            };
+
+            lines.push(format!("{}. {date} — {status}", i + 1));
+        }
+
+        self.bot
+            .send_message(self.msg.chat.id, lines.join("\n"))
+            .reply_markup(super::markups::get_my_markup(&photos))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ban(&self, args: &str) -> anyhow::Result<()> {
         let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
 
-        if manager.admin_id != cmd_user {
+        if !Moderators::can_moderate(cmd_user).await {
             return Ok(());
         }
 
-        if let Some(reply) = self.msg.reply_to_message() {
-            let photo = Photos::get_by_msg_id(reply.id.0).await;
+        let duration = match args.trim() {
+            "" => None,
+            d => match parse_duration(d) {
+                Some(d) => Some(d.num_seconds()),
+                None => {
+                    self.bot.send_message(self.msg.chat.id, t!("messages.ban_usage")).await?;
 
-            let state = BanUser {
-                user_id: photo.unwrap().user_id,
-                ..Default::default()
-            };
+                    return Ok(());
+                }
+            },
+        };
+
+        let reply = match self.msg.reply_to_message() {
+            Some(r) => r,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.ban_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        let card = match ModerationCards::get_by_msg(self.msg.chat.id.0, reply.id.0).await {
+            Some(c) => c,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.ban_reply_not_card")).await?;
+
+                return Ok(());
+            }
+        };
+
+        let (user_id, photo_id) = match (card.photo_uuid, card.submission_uuid) {
+            (Some(uuid), _) => (Photos::get_by_id(uuid).await.map(|p| p.user_id), Some(uuid)),
+            (None, Some(uuid)) => (Submissions::get_by_id(uuid).await.map(|s| s.user_id), None),
+            (None, None) => (None, None),
+        };
+
+        let user_id = match user_id {
+            Some(u) => u,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.ban_author_not_found")).await?;
+
+                return Ok(());
+            }
+        };
+
+        let state = BanUser {
+            user_id,
+            photo_id,
+            duration,
+            ..Default::default()
+        };
+
+        if state.set(cmd_user).await {
+            self.dialogue.update(GlobalState::BanUser(State::Reason)).await?;
+
+            self.bot
+                .send_message(self.msg.chat.id, t!("messages.enter_ban_reason"))
+                .reply_markup(super::markups::get_cancel_markup())
+                .await?;
+        };
+
+        // The bot can't delete messages of others in a group without admin rights, the ban works anyway
+        if let Err(e) = self.bot.delete_message(self.msg.chat.id, self.msg.id).await {
+            warn!("Can't delete ban command: {e}");
+        }
+
+        Ok(())
+    }
+
+    async fn unban(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::can_moderate(cmd_user).await {
+            return Ok(());
+        }
 
-            if state.set(cmd_user).await {
-                self.dialogue.update(GlobalState::BanUser(State::Reason)).await?;
+        let user_id = match Users::resolve(args.trim()).await {
+            Some(u) => u,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.unban_usage")).await?;
 
+                return Ok(());
+            }
+        };
+
+        let text = if unban_user(user_id, cmd_user).await {
+            t!("messages.user_unbanned", user_id = user_id)
+        } else {
+            t!("messages.ban_not_found")
+        };
+
+        self.bot.send_message(self.msg.chat.id, text).await?;
+
+        Ok(())
+    }
+
+    async fn bans(&self) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::can_moderate(cmd_user).await {
+            return Ok(());
+        }
+
+        let (text, markup) = render_bans(0).await;
+
+        self.bot.send_message(self.msg.chat.id, text).reply_markup(markup).await?;
+
+        Ok(())
+    }
+
+    async fn caption(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::can_moderate(cmd_user).await {
+            return Ok(());
+        }
+
+        let photo_uuid = match self.msg.reply_to_message() {
+            Some(reply) => ModerationCards::get_by_msg(self.msg.chat.id.0, reply.id.0)
+                .await
+                .and_then(|c| c.photo_uuid),
+            None => None,
+        };
+        let photo = match photo_uuid {
+            Some(uuid) => Photos::get_by_id(uuid).await,
+            None => None,
+        };
+
+        let (photo, caption) = match (photo, args.trim()) {
+            (Some(p), "-") => (p, String::new()),
+            (Some(p), c) if !c.is_empty() && c.chars().count() <= photos::MAX_CAPTION_LENGTH => (p, c.to_string()),
+            _ => {
                 self.bot
-                    .send_message(self.msg.chat.id, t!("messages.enter_ban_reason"))
-                    .reply_markup(super::markups::get_cancel_markup())
+                    .send_message(self.msg.chat.id, t!("messages.caption_usage", max = photos::MAX_CAPTION_LENGTH))
                     .await?;
+
+                return Ok(());
+            }
+        };
+
+        if !photo.is_editable() {
+            self.bot.send_message(self.msg.chat.id, t!("messages.photo_already_processed")).await?;
+
+            return Ok(());
+        }
+
+        if save_caption(&photo, caption).await {
+            self.bot.send_message(self.msg.chat.id, t!("messages.caption_updated")).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn limits(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::can_moderate(cmd_user).await {
+            return Ok(());
+        }
+
+        let parts: Vec<&str> = args.split_whitespace().collect();
+        let user_id = match parts.first() {
+            Some(target) => Users::resolve(target).await,
+            None => None,
+        };
+        let limits = match parts.get(1..) {
+            Some(["reset"]) => Some((None, None)),
+            Some([hourly, pending]) => match (hourly.parse::<i32>(), pending.parse::<i32>()) {
+                (Ok(h), Ok(p)) if h >= 0 && p >= 0 => Some((Some(h), Some(p))),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let (user_id, (hourly, pending)) = match (user_id, limits) {
+            (Some(u), Some(l)) => (u, l),
+            _ => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.limits_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        if !Users::set_limits(user_id, hourly, pending).await {
+            self.bot.send_message(self.msg.chat.id, t!("messages.user_not_found")).await?;
+
+            return Ok(());
+        }
+
+        let text = match (hourly, pending) {
+            (Some(h), Some(p)) => t!("messages.limits_updated", user_id = user_id, hourly = h, pending = p),
+            _ => t!("messages.limits_reset", user_id = user_id),
+        };
+
+        self.bot.send_message(self.msg.chat.id, text).await?;
+
+        Ok(())
+    }
+
+    async fn add_moderator(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::is_owner(cmd_user).await {
+            return Ok(());
+        }
+
+        let mut parts = args.split_whitespace();
+        let user_id = match parts.next() {
+            Some(target) => Users::resolve(target).await,
+            None => None,
+        };
+        let role = match parts.next() {
+            Some(r) => ModeratorRole::try_from(r).ok(),
+            None => Some(ModeratorRole::Moderator),
+        };
+
+        let (user_id, role) = match (user_id, role) {
+            (Some(u), Some(r)) => (u, r),
+            _ => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.add_moderator_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        if user_id == cmd_user {
+            self.bot.send_message(self.msg.chat.id, t!("messages.moderator_self_change")).await?;
+
+            return Ok(());
+        }
+
+        if Moderators::set(user_id, role, Some(cmd_user)).await {
+            self.bot
+                .send_message(self.msg.chat.id, t!("messages.moderator_added", user_id = user_id, role = role.name()))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_moderator(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::is_owner(cmd_user).await {
+            return Ok(());
+        }
+
+        let user_id = match Users::resolve(args.trim()).await {
+            Some(u) => u,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.remove_moderator_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        if user_id == cmd_user {
+            self.bot.send_message(self.msg.chat.id, t!("messages.moderator_self_change")).await?;
+
+            return Ok(());
+        }
+
+        let text = if Moderators::remove(user_id).await {
+            t!("messages.moderator_removed", user_id = user_id)
+        } else {
+            t!("messages.moderator_not_found")
+        };
+
+        self.bot.send_message(self.msg.chat.id, text).await?;
+
+        Ok(())
+    }
+
+    async fn moderators(&self) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if Moderators::get(cmd_user).await.is_none() {
+            return Ok(());
+        }
+
+        let mut lines = vec![t!("messages.moderators_list").to_string()];
+
+        for moderator in Moderators::all().await {
+            let name = match Users::get(moderator.user_id).await {
+                Some(u) => u.mention_or_url(),
+                None => moderator.user_id.to_string(),
+            };
+
+            lines.push(format!("• {name} — {}", moderator.role.name()));
+        }
+
+        self.bot.send_message(self.msg.chat.id, lines.join("\n")).await?;
+
+        Ok(())
+    }
+
+    async fn history(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if Moderators::get(cmd_user).await.is_none() {
+            return Ok(());
+        }
+
+        let photo = match self.find_photo(args).await {
+            Some(p) => p,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.history_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        let events = ModerationEvents::get_by_photo(photo.uuid).await;
+
+        if events.is_empty() {
+            self.bot.send_message(self.msg.chat.id, t!("messages.history_empty")).await?;
+
+            return Ok(());
+        }
+
+        let mut lines = vec![t!("messages.history_title", uuid = photo.uuid).to_string()];
+
+        for event in events {
+            let moderator = match Users::get(event.moderator_id).await {
+                Some(u) => u.mention_or_url(),
+                None => event.moderator_id.to_string(),
             };
+            let date = event.created_at.map(|d| d.format("%d.%m.%Y %H:%M").to_string()).unwrap_or_default();
+            let mut line = format!("{date} — {} — {moderator}", event.action.name());
+
+            if let Some(reason) = event.reason {
+                line.push_str(&format!(": {reason}"));
+            }
+
+            lines.push(line);
         }
 
-        self.bot.delete_message(self.msg.chat.id, self.msg.id).await?;
+        self.bot.send_message(self.msg.chat.id, lines.join("\n")).await?;
 
         Ok(())
     }
+
+    /// Puts photos which couldn't be posted back to the queue, the whole album when the photo is a part of it
+    async fn requeue(&self, args: &str) -> anyhow::Result<()> {
+        let cmd_user = self.msg.from.as_ref().unwrap().id.0 as i64;
+
+        if !Moderators::can_moderate(cmd_user).await {
+            return Ok(());
+        }
+
+        let photo = match self.find_photo(args).await {
+            Some(p) => p,
+            None => {
+                self.bot.send_message(self.msg.chat.id, t!("messages.requeue_usage")).await?;
+
+                return Ok(());
+            }
+        };
+
+        let photos = match photo.submission().await {
+            Some(submission) => submission.photos().await,
+            None => vec![photo],
+        };
+        let mut requeued = 0;
+
+        for photo in photos.iter().filter(|p| p.status == PhotoStatus::Failed) {
+            if photo.requeue().await {
+                ModerationEvents::add(Some(photo.uuid), cmd_user, ModerationAction::Requeue, None).await;
+                requeued += 1;
+            }
+        }
+
+        let text = match requeued {
+            0 => t!("messages.requeue_not_failed"),
+            _ => t!("messages.photo_requeued", count = requeued),
+        };
+
+        self.bot.send_message(self.msg.chat.id, text).await?;
+
+        Ok(())
+    }
+
+    /// Photo of the moderation card the command replies to, or the one with the uuid from arguments
+    async fn find_photo(&self, args: &str) -> Option<photos::Model> {
+        let photo_uuid = match self.msg.reply_to_message() {
+            Some(reply) => ModerationCards::get_by_msg(self.msg.chat.id.0, reply.id.0)
+                .await
+                .and_then(|c| c.photo_uuid),
+            None => Uuid::parse_str(args.trim()).ok(),
+        };
+
+        Photos::get_by_id(photo_uuid?).await
+    }
+}
+
+/// Saves moderator's caption, an empty one removes the caption from the post
+pub(super) async fn save_caption(photo: &photos::Model, caption: String) -> bool {
+    if !photo.set_caption_override(Some(caption.clone())).await {
+        error!("Can't update caption of photo {}", photo.uuid);
+
+        return false;
+    }
+
+    let photo = photos::Model {
+        caption_override: Some(caption),
+        ..photo.clone()
+    };
+
+    BotManager::global().refresh_photo_cards(&photo).await;
+
+    true
+}
+
+pub(super) async fn unban_user(user_id: i64, moderator_id: i64) -> bool {
+    if !Ban::unban(user_id).await {
+        return false;
+    }
+
+    ModerationEvents::add_for_user(user_id, None, moderator_id, ModerationAction::Unban, None).await;
+
+    true
+}
+
+pub(super) async fn render_bans(page: u64) -> (String, InlineKeyboardMarkup) {
+    let (bans, pages) = Ban::get_active_page(page, BANS_PER_PAGE).await;
+
+    if bans.is_empty() {
+        return (t!("messages.bans_empty").to_string(), InlineKeyboardMarkup::default());
+    }
+
+    let mut lines = vec![t!("messages.bans_title", page = page + 1, pages = pages).to_string()];
+    let mut buttons = Vec::with_capacity(bans.len());
+
+    for ban in bans {
+        let user = Users::get(ban.user_id).await;
+        let name = match &user {
+            Some(u) => u.mention_or_url(),
+            None => ban.user_id.to_string(),
+        };
+        let label = match user {
+            Some(u) => u.username.map(|n| format!("@{n}")).unwrap_or(u.firstname),
+            None => ban.user_id.to_string(),
+        };
+        let until = match ban.expires_at {
+            Some(e) => e.format("%d.%m.%Y %H:%M UTC").to_string(),
+            None => t!("messages.ban_forever").to_string(),
+        };
+
+        lines.push(format!(
+            "• {name} ({}) — {until}: {}",
+            ban.user_id,
+            ban.reason.clone().unwrap_or_default()
+        ));
+        buttons.push((ban, label));
+    }
+
+    (lines.join("\n"), super::markups::get_bans_markup(&buttons, page, pages))
+}
+
+/// Parses durations like `30m`, `12h`, `7d` or `2w`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (amount, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit())?);
+    let amount = amount.parse::<i64>().ok().filter(|a| *a > 0)?;
+
+    match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    }
 }
 
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
     dptree::entry().branch(
         Update::filter_message()
-            .enter_dialogue::<Message, RedisStorage<Json>, GlobalState>()
-            .filter(|m: Message| m.chat.is_private())
+            .chain(super::dialogue::enter())
+            .filter(|m: Message| m.chat.is_private() || BotManager::global().is_moderators_chat(m.chat.id))
             .filter_command::<BotCommand>()
             .endpoint(CommandHandler::handle),
     )
@@ -1,14 +1,13 @@
-use crate::bot::{Bot, BotDialogue, GlobalState, traits::DialogueContext};
-use crate::db::entity::prelude::Ban;
+use crate::bot::{Bot, BotDialogue, BotManager, GlobalState, traits::DialogueContext};
+use crate::db::entity::{
+    moderation_events::ModerationAction,
+    prelude::{Ban, ModerationEvents, Photos, Submissions},
+};
+use crate::types::CanMention;
 use anyhow::Result;
+use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
-use teloxide::{
-    dispatching::{
-        UpdateHandler,
-        dialogue::{RedisStorage, serializer::Json},
-    },
-    prelude::*,
-};
+use teloxide::{dispatching::UpdateHandler, prelude::*};
 
 use super::types::BanUser;
 
@@ -31,20 +30,80 @@ async fn set_reason(bot: Bot, msg: Message, dialogue: BotDialogue) -> Result<()>
     if let Some(mut state) = BanUser::get(user_id).await {
         state.reason = msg.text().map(str::to_string);
 
-        Ban::user(state.user_id, &state.reason.unwrap()).await;
+        let reason = state.reason.unwrap_or_default();
+        let expires_at = state.duration.and_then(Duration::try_seconds).map(|d| Utc::now().naive_utc() + d);
+
+        if !Ban::user(state.user_id, &reason, expires_at, Some(user_id)).await {
+            error!("Can't ban user {}", state.user_id);
+
+            return Ok(());
+        }
+
+        ModerationEvents::add_for_user(state.user_id, state.photo_id, user_id, ModerationAction::Ban, Some(reason.clone())).await;
 
         bot.send_message(msg.chat.id, t!("messages.user_banned")).await?;
 
         dialogue.update(GlobalState::Idle).await?;
+
+        let moderator = msg.from.as_ref().map(|u| u.mention_or_url()).unwrap_or_default();
+
+        decline_pending(
+            state.user_id,
+            user_id,
+            &t!("messages.card_banned", moderator = moderator, reason = &reason),
+        )
+        .await;
+
+        if BotManager::global().notify_banned() {
+            let text = match expires_at {
+                Some(e) => t!(
+                    "messages.you_are_banned_until",
+                    reason = reason,
+                    until = e.format("%d.%m.%Y %H:%M UTC").to_string()
+                ),
+                None => t!("messages.you_are_banned", reason = reason),
+            };
+
+            if let Err(e) = bot.send_message(ChatId(state.user_id), text).await {
+                warn!("Can't notify banned user {}: {e}", state.user_id);
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Banned author's photos waiting for moderation are declined at once, so nobody has to go through them
+async fn decline_pending(user_id: i64, moderator_id: i64, decision: &str) {
+    let manager = BotManager::global();
+    let mut submissions = Vec::new();
+
+    for photo in Photos::get_pending_by_user(user_id).await {
+        if !photo.decline(None).await {
+            continue;
+        }
+
+        ModerationEvents::add(Some(photo.uuid), moderator_id, ModerationAction::Decline, None).await;
+        manager.resolve_photo_cards(&photo, decision).await;
+
+        if let Some(uuid) = photo.submission_uuid
+            && !submissions.contains(&uuid)
+        {
+            submissions.push(uuid);
+        }
+    }
+
+    for uuid in submissions {
+        if let Some(submission) = Submissions::get_by_id(uuid).await {
+            manager.resolve_submission_cards(&submission, decision).await;
+        }
+    }
+}
+
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
     dptree::entry().branch(
         Update::filter_message()
-            .enter_dialogue::<Message, RedisStorage<Json>, GlobalState>()
+            .chain(super::enter())
             .branch(dptree::case![GlobalState::BanUser(x)].branch(dptree::case![State::Reason].endpoint(set_reason))),
     )
 }
@@ -1,18 +1,16 @@
 use crate::{
-    bot::{Bot, BotDialogue, GlobalState, traits::DialogueContext},
+    bot::{Bot, BotDialogue, BotManager, GlobalState, traits::DialogueContext},
+    db::entity::{
+        moderation_events::ModerationAction,
+        prelude::{ModerationEvents, Photos, Submissions},
+    },
     redis::{RedisManager, types::QueueMessage},
+    types::CanMention,
 };
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use teloxide::{
-    dispatching::{
-        UpdateHandler,
-        dialogue::{GetChatId, RedisStorage, serializer::Json},
-    },
-    prelude::*,
-    types::MessageId,
-};
+use teloxide::{dispatching::UpdateHandler, prelude::*};
 
 use super::types::DeclinePhoto;
 
@@ -23,7 +21,6 @@ pub enum State {
 }
 
 #[allow(dead_code)]
-#[allow(unused_variables)]
 async fn set_reason(bot: Bot, msg: Message, dialogue: BotDialogue) -> Result<()> {
     let user_id = match &msg.from {
         Some(f) => f.id.0 as i64,
@@ -34,20 +31,52 @@ async fn set_reason(bot: Bot, msg: Message, dialogue: BotDialogue) -> Result<()>
         }
     };
 
-    if let Some(mut state) = DeclinePhoto::get(user_id).await {
-        state.reason = msg.text().map(str::to_string);
+    if let Some(state) = DeclinePhoto::get(user_id).await {
+        let reason = msg.text().map(str::to_string).unwrap_or_default();
+
+        dialogue.update(GlobalState::Idle).await?;
+
+        let submission = match state.submission_id {
+            Some(uuid) => Submissions::get_by_id(uuid).await,
+            None => None,
+        };
+        let photos = match &submission {
+            Some(s) => s.photos().await.into_iter().filter(|p| p.is_pending()).collect(),
+            None => Photos::get_by_id(state.photo_id).await.into_iter().collect::<Vec<_>>(),
+        };
 
-        let redis = RedisManager::global();
+        // Status changes right away, so an approve pressed by another moderator meanwhile loses the race
+        let mut declined = Vec::with_capacity(photos.len());
 
-        redis
-            .add_queue_item(&json!(QueueMessage::decline(state.photo_id, state.reason.unwrap_or_default())))
+        for photo in photos {
+            if photo.decline(Some(reason.clone())).await {
+                ModerationEvents::add(Some(photo.uuid), user_id, ModerationAction::Decline, Some(reason.clone())).await;
+                declined.push(photo);
+            }
+        }
+
+        let first = match declined.first() {
+            Some(p) => p,
+            None => {
+                bot.send_message(msg.chat.id, t!("messages.photo_already_processed")).await?;
+
+                return Ok(());
+            }
+        };
+
+        // The subscriber only notifies the author
+        RedisManager::global()
+            .add_queue_item(&json!(QueueMessage::decline(first.uuid, reason.clone())))
             .await;
 
-        dialogue.update(GlobalState::Idle).await?;
+        let manager = BotManager::global();
+        let moderator = msg.from.as_ref().unwrap().mention_or_url();
+        let decision = t!("messages.card_declined", moderator = moderator, reason = reason);
 
-        let photo = crate::db::entity::prelude::Photos::get_by_id(state.photo_id).await.unwrap();
-        bot.delete_message(msg.chat_id().unwrap(), MessageId(photo.msg_id.unwrap() as i32))
-            .await?;
+        match &submission {
+            Some(s) => manager.resolve_submission_cards(s, &decision).await,
+            None => manager.resolve_photo_cards(first, &decision).await,
+        }
     }
 
     Ok(())
@@ -57,7 +86,7 @@ async fn set_reason(bot: Bot, msg: Message, dialogue: BotDialogue) -> Result<()>
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
     dptree::entry().branch(
         Update::filter_message()
-            .enter_dialogue::<Message, RedisStorage<Json>, GlobalState>()
+            .chain(super::enter())
             .branch(dptree::case![GlobalState::DeclinePhoto(x)].branch(dptree::case![State::Reason].endpoint(set_reason))),
     )
 }
@@ -0,0 +1,136 @@
+use crate::bot::types::{CallbackData, CallbackOperation};
+use crate::bot::{Bot, BotDialogue, BotManager, GlobalState, command, markups, traits::DialogueContext};
+use crate::db::entity::{photos, prelude::Photos};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use teloxide::{dispatching::UpdateHandler, prelude::*};
+
+use super::types::EditCaption;
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub enum State {
+    #[default]
+    Caption,
+    Confirm,
+}
+
+async fn set_caption(bot: Bot, msg: Message, dialogue: BotDialogue) -> Result<()> {
+    let user_id = match &msg.from {
+        Some(f) => f.id.0 as i64,
+        None => {
+            error!("User is empty");
+
+            return Ok(());
+        }
+    };
+
+    let mut state = match EditCaption::get(user_id).await {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    let caption = match msg.text().map(str::trim) {
+        Some("-") => String::new(),
+        Some(c) if c.chars().count() <= photos::MAX_CAPTION_LENGTH => c.to_string(),
+        Some(_) => {
+            bot.send_message(msg.chat.id, t!("messages.caption_too_long", max = photos::MAX_CAPTION_LENGTH))
+                .await?;
+
+            return Ok(());
+        }
+        None => {
+            bot.send_message(msg.chat.id, t!("messages.caption_text_only")).await?;
+
+            return Ok(());
+        }
+    };
+
+    let photo = match Photos::get_by_id(state.photo_id).await {
+        Some(p) => p,
+        None => {
+            error!("No photo found");
+            dialogue.update(GlobalState::Idle).await?;
+
+            return Ok(());
+        }
+    };
+
+    let preview = BotManager::global()
+        .get_post_caption_preview(&photos::Model {
+            caption_override: Some(caption.clone()),
+            ..photo
+        })
+        .await;
+
+    state.caption = Some(caption);
+
+    if state.set(user_id).await {
+        dialogue.update(GlobalState::EditCaption(State::Confirm)).await?;
+
+        bot.send_message(msg.chat.id, t!("messages.caption_preview", preview = preview))
+            .reply_markup(markups::get_caption_confirm_markup())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The dialogue is finished only when there is nothing left to save, so a failed save can be repeated with the same button
+async fn save_caption(bot: Bot, callback: CallbackQuery, dialogue: BotDialogue) -> Result<()> {
+    let user_id = callback.from.id.0 as i64;
+    let (photo, caption) = match EditCaption::get(user_id).await {
+        Some(EditCaption { photo_id, caption: Some(c) }) => (Photos::get_by_id(photo_id).await, c),
+        _ => (None, String::new()),
+    };
+
+    let text = match photo {
+        Some(p) if !p.is_editable() => t!("messages.photo_already_processed"),
+        Some(p) if command::save_caption(&p, caption).await => t!("messages.caption_updated"),
+        Some(_) => {
+            error!("Can't save caption for user {user_id}");
+
+            bot.answer_callback_query(callback.id.clone())
+                .text(t!("messages.caption_save_failed"))
+                .await?;
+
+            return Ok(());
+        }
+        None => {
+            error!("Caption of user {user_id} has been lost");
+
+            t!("messages.caption_save_failed")
+        }
+    };
+
+    dialogue.update(GlobalState::Idle).await?;
+
+    bot.answer_callback_query(callback.id.clone()).text(text).await?;
+
+    if let Some(msg) = callback.regular_message() {
+        bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+    }
+
+    Ok(())
+}
+
+fn is_save(callback: CallbackQuery) -> bool {
+    callback
+        .data
+        .and_then(|d| serde_json::from_str::<CallbackData>(&d).ok())
+        .is_some_and(|d| matches!(d.operation, CallbackOperation::SaveCaption))
+}
+
+pub fn scheme() -> UpdateHandler<anyhow::Error> {
+    dptree::entry()
+        .branch(
+            Update::filter_message()
+                .chain(super::enter())
+                .branch(dptree::case![GlobalState::EditCaption(x)].endpoint(set_caption)),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .filter(is_save)
+                .chain(super::enter())
+                .branch(dptree::case![GlobalState::EditCaption(x)].branch(dptree::case![State::Confirm].endpoint(save_caption))),
+        )
+}
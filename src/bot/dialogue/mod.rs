@@ -1,4 +1,5 @@
 use anyhow::Result;
+use std::sync::Arc;
 use teloxide::{
     dispatching::{
         UpdateHandler,
@@ -14,6 +15,7 @@ use super::{
 
 pub mod ban_user;
 pub mod decline_photo;
+pub mod edit_caption;
 pub mod types;
 
 async fn cancel_callback(bot: Bot, callback: CallbackQuery, dialogue: BotDialogue) -> Result<()> {
@@ -47,11 +49,33 @@ async fn cancel_callback(bot: Bot, callback: CallbackQuery, dialogue: BotDialogu
     Ok(())
 }
 
+/// Like `enter_dialogue`, but the state is kept per user rather than per chat,
+/// otherwise a prompt for one moderator in the moderators chat would catch messages of everybody there.
+/// In private chats both ids are the same
+pub(super) fn enter() -> UpdateHandler<anyhow::Error> {
+    dptree::filter_map(|storage: Arc<RedisStorage<Json>>, update: Update| update.from().map(|u| BotDialogue::new(storage, ChatId(u.id.0 as i64))))
+        .filter_map_async(|dialogue: BotDialogue| async move {
+            match dialogue.get_or_default().await {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    error!("Can't get dialogue state: {e:?}");
+
+                    None
+                }
+            }
+        })
+}
+
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
-    dptree::entry().branch(ban_user::scheme()).branch(decline_photo::scheme()).branch(
-        Update::filter_callback_query()
-            .enter_dialogue::<CallbackQuery, RedisStorage<Json>, GlobalState>()
-            .branch(dptree::case![GlobalState::BanUser(x)].endpoint(cancel_callback))
-            .branch(dptree::case![GlobalState::DeclinePhoto(x)].endpoint(cancel_callback)),
-    )
+    dptree::entry()
+        .branch(ban_user::scheme())
+        .branch(decline_photo::scheme())
+        .branch(edit_caption::scheme())
+        .branch(
+            Update::filter_callback_query()
+                .chain(enter())
+                .branch(dptree::case![GlobalState::BanUser(x)].endpoint(cancel_callback))
+                .branch(dptree::case![GlobalState::DeclinePhoto(x)].endpoint(cancel_callback))
+                .branch(dptree::case![GlobalState::EditCaption(x)].endpoint(cancel_callback)),
+        )
 }
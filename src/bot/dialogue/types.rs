@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct BanUser {
     pub user_id: i64,
+    pub photo_id: Option<uuid::Uuid>,
+    pub duration: Option<i64>,
     pub reason: Option<String>,
 }
 
@@ -12,7 +14,16 @@ impl DialogueContext for BanUser {}
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct DeclinePhoto {
     pub photo_id: uuid::Uuid,
+    pub submission_id: Option<uuid::Uuid>,
     pub reason: Option<String>,
 }
 
 impl DialogueContext for DeclinePhoto {}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct EditCaption {
+    pub photo_id: uuid::Uuid,
+    pub caption: Option<String>,
+}
+
+impl DialogueContext for EditCaption {}
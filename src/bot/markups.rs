@@ -1,7 +1,9 @@
 use serde_json::json;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
-use crate::db::entity::photos::Model;
+use crate::db::entity::{ban::Model as BanModel, photos::Model, submissions::Model as Submission};
+use crate::watermark::Watermark;
+use uuid::Uuid;
 
 use super::types::{CallbackData, CallbackOperation};
 
@@ -12,23 +14,194 @@ pub fn get_cancel_markup() -> InlineKeyboardMarkup {
     )]])
 }
 
-pub fn get_document_markup(model: &Model) -> InlineKeyboardMarkup {
+pub fn get_caption_confirm_markup() -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(vec![vec![
-        InlineKeyboardButton::callback(
-            t!("buttons.approve"),
-            json!(CallbackData {
-                operation: CallbackOperation::Approve,
-                document: Some(model.uuid)
+        InlineKeyboardButton::callback(t!("buttons.save"), json!(CallbackData::new(CallbackOperation::SaveCaption)).to_string()),
+        InlineKeyboardButton::callback(t!("buttons.cancel"), json!(CallbackData::new(CallbackOperation::Cancel)).to_string()),
+    ]])
+}
+
+/// Buttons of the moderation card, the watermark toggle appears only when watermarking is configured
+pub fn get_document_markup(model: &Model) -> InlineKeyboardMarkup {
+    let mut rows = vec![
+        vec![
+            InlineKeyboardButton::callback(
+                t!("buttons.approve"),
+                json!(CallbackData::with_document(CallbackOperation::Approve, model.uuid)).to_string(),
+            ),
+            InlineKeyboardButton::callback(
+                t!("buttons.decline"),
+                json!(CallbackData::with_document(CallbackOperation::Decline, model.uuid)).to_string(),
+            ),
+        ],
+        vec![
+            InlineKeyboardButton::callback(
+                t!("buttons.edit_caption"),
+                json!(CallbackData::with_document(CallbackOperation::EditCaption, model.uuid)).to_string(),
+            ),
+            InlineKeyboardButton::callback(
+                t!("buttons.ban_author"),
+                json!(CallbackData::with_document(CallbackOperation::BanAuthor, model.uuid)).to_string(),
+            ),
+        ],
+    ];
+
+    if Watermark::global().is_enabled() {
+        let label = if model.watermark {
+            t!("buttons.watermark_on")
+        } else {
+            t!("buttons.watermark_off")
+        };
+
+        rows.push(vec![InlineKeyboardButton::callback(
+            label,
+            json!(CallbackData::with_document(CallbackOperation::ToggleWatermark, model.uuid)).to_string(),
+        )]);
+    }
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Buttons under the thanks message, the location toggle appears only when the photos have coordinates
+pub fn get_thanks_markup(photo: &Model, has_location: bool) -> InlineKeyboardMarkup {
+    let mut rows = vec![vec![InlineKeyboardButton::callback(
+        t!("buttons.withdraw"),
+        json!(CallbackData::with_document(CallbackOperation::Withdraw, photo.uuid)).to_string(),
+    )]];
+
+    if has_location {
+        let label = if photo.publish_location {
+            t!("buttons.publish_location_on")
+        } else {
+            t!("buttons.publish_location_off")
+        };
+
+        rows.push(vec![InlineKeyboardButton::callback(
+            label,
+            json!(CallbackData::with_document(CallbackOperation::ToggleLocation, photo.uuid)).to_string(),
+        )]);
+    }
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Withdraw buttons for pending photos from /my listing, numbers match the listing
+pub fn get_my_markup(photos: &[Model]) -> InlineKeyboardMarkup {
+    let buttons: Vec<InlineKeyboardButton> = photos
+        .iter()
+        .enumerate()
+        .filter(|(_, photo)| photo.is_pending())
+        .map(|(i, photo)| {
+            InlineKeyboardButton::callback(
+                t!("buttons.withdraw_number", number = i + 1),
+                json!(CallbackData::with_document(CallbackOperation::Withdraw, photo.uuid)).to_string(),
+            )
+        })
+        .collect();
+
+    InlineKeyboardMarkup::new(buttons.chunks(5).map(|c| c.to_vec()))
+}
+
+/// Buttons of the album card, photos are numbered in the order of the album.
+/// Per-photo caption and watermark buttons live here as documents of a media group can't have buttons
+pub fn get_album_markup(submission: &Submission, photos: &[Model], excluded: &[Uuid]) -> InlineKeyboardMarkup {
+    let toggles: Vec<InlineKeyboardButton> = photos
+        .iter()
+        .enumerate()
+        .map(|(i, photo)| {
+            let label = if excluded.contains(&photo.uuid) {
+                t!("buttons.photo_excluded", number = i + 1)
+            } else {
+                t!("buttons.photo_included", number = i + 1)
+            };
+
+            InlineKeyboardButton::callback(
+                label,
+                json!(CallbackData::with_document(CallbackOperation::TogglePhoto, photo.uuid)).to_string(),
+            )
+        })
+        .collect();
+
+    let captions: Vec<InlineKeyboardButton> = photos
+        .iter()
+        .enumerate()
+        .map(|(i, photo)| {
+            InlineKeyboardButton::callback(
+                t!("buttons.edit_caption_number", number = i + 1),
+                json!(CallbackData::with_document(CallbackOperation::EditCaption, photo.uuid)).to_string(),
+            )
+        })
+        .collect();
+
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = toggles.chunks(5).map(|c| c.to_vec()).collect();
+
+    rows.extend(captions.chunks(5).map(|c| c.to_vec()));
+
+    if Watermark::global().is_enabled() {
+        let watermarks: Vec<InlineKeyboardButton> = photos
+            .iter()
+            .enumerate()
+            .map(|(i, photo)| {
+                let label = if photo.watermark {
+                    t!("buttons.watermark_on_number", number = i + 1)
+                } else {
+                    t!("buttons.watermark_off_number", number = i + 1)
+                };
+
+                InlineKeyboardButton::callback(
+                    label,
+                    json!(CallbackData::with_document(CallbackOperation::ToggleWatermark, photo.uuid)).to_string(),
+                )
             })
-            .to_string(),
+            .collect();
+
+        rows.extend(watermarks.chunks(5).map(|c| c.to_vec()));
+    }
+
+    rows.push(vec![
+        InlineKeyboardButton::callback(
+            t!("buttons.approve_selected"),
+            json!(CallbackData::with_document(CallbackOperation::ApproveAlbum, submission.uuid)).to_string(),
         ),
         InlineKeyboardButton::callback(
-            t!("buttons.decline"),
-            json!(CallbackData {
-                operation: CallbackOperation::Decline,
-                document: Some(model.uuid)
-            })
-            .to_string(),
+            t!("buttons.decline_all"),
+            json!(CallbackData::with_document(CallbackOperation::DeclineAlbum, submission.uuid)).to_string(),
         ),
-    ]])
+    ]);
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+pub fn get_bans_markup(bans: &[(BanModel, String)], page: u64, pages: u64) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = bans
+        .iter()
+        .map(|(ban, name)| {
+            vec![InlineKeyboardButton::callback(
+                t!("buttons.unban", name = name),
+                json!(CallbackData::with_user(CallbackOperation::Unban, ban.user_id)).to_string(),
+            )]
+        })
+        .collect();
+
+    let mut navigation = Vec::with_capacity(2);
+
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            t!("buttons.previous_page"),
+            json!(CallbackData::with_page(CallbackOperation::BansPage, page - 1)).to_string(),
+        ));
+    }
+
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            t!("buttons.next_page"),
+            json!(CallbackData::with_page(CallbackOperation::BansPage, page + 1)).to_string(),
+        ));
+    }
+
+    if !navigation.is_empty() {
+        rows.push(navigation);
+    }
+
+    InlineKeyboardMarkup::new(rows)
 }
@@ -1,16 +1,18 @@
 use crate::bot::{Bot, BotManager};
-use crate::db::entity::prelude::{Ban, Photos, Users};
+use crate::db::entity::{
+    photos::{self, Rejection},
+    prelude::{Ban, ModerationCards, Photos, Submissions, Users},
+};
+use crate::redis::RedisManager;
 use crate::types::CanMention;
+use sea_orm::Set;
+use std::time::Duration;
 use teloxide::{
-    dispatching::{
-        UpdateHandler,
-        dialogue::{RedisStorage, serializer::Json},
-    },
+    dispatching::UpdateHandler,
     prelude::*,
-    types::{Document, InputFile, MessageKind},
+    types::{MessageKind, User},
 };
-
-use super::GlobalState;
+use uuid::Uuid;
 
 pub struct MessageHandler {
     pub bot: Bot,
@@ -18,6 +20,9 @@ pub struct MessageHandler {
 }
 
 const MAX_FILE_SIZE: u32 = 15 * 1024 * 1024;
+const SUBMISSIONS_WINDOW: u64 = 60 * 60;
+// Telegram delivers every document of a media group as a separate update
+const ALBUM_COLLECT_DELAY: Duration = Duration::from_secs(3);
 
 impl MessageHandler {
     pub async fn handle(bot: Bot, msg: Message) -> anyhow::Result<()> {
@@ -41,10 +46,11 @@ impl MessageHandler {
     async fn private(&self) -> anyhow::Result<()> {
         if let Some(doc) = self.msg.document() {
             if let Some(doc_mime) = doc.to_owned().mime_type {
-                match doc_mime.type_() {
-                    mime::IMAGE => {
+                // Some clients send RAW files without a proper MIME type, the real type is detected by the content later
+                match (doc_mime.type_(), doc_mime.subtype()) {
+                    (mime::IMAGE, _) | (mime::APPLICATION, mime::OCTET_STREAM) => {
                         if doc.to_owned().file.size < MAX_FILE_SIZE {
-                            return self.send_to_moderation(doc).await;
+                            return self.send_to_moderation().await;
                         }
 
                         self.bot.send_message(self.msg.chat.id, t!("messages.max_filesize_reached")).await?;
@@ -63,8 +69,17 @@ impl MessageHandler {
         Ok(())
     }
 
-    async fn send_to_moderation(&self, doc: &Document) -> anyhow::Result<()> {
-        let bot = BotManager::global();
+    async fn send_to_moderation(&self) -> anyhow::Result<()> {
+        if let Some(media_group_id) = self.msg.media_group_id() {
+            return self.add_to_album(&media_group_id.0).await;
+        }
+
+        if let Some(text) = check_limits(self.msg.chat.id.0).await {
+            self.bot.send_message(self.msg.chat.id, text).await?;
+
+            return Ok(());
+        }
+
         let model = match Photos::add(self.msg.clone().into()).await {
             Some(m) => m,
             None => {
@@ -73,25 +88,268 @@ impl MessageHandler {
             }
         };
 
-        let msg = self
-            .bot
-            .send_document(ChatId(bot.get_admin_id()), InputFile::file_id(doc.to_owned().file.id))
-            .caption(format!("Автор: {}", self.msg.from.as_ref().unwrap().mention_or_url()))
-            .reply_markup(super::markups::get_document_markup(&model))
+        let model = match check_photo(&self.bot, self.msg.chat.id, model).await? {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+
+        BotManager::global()
+            .send_photo_card(&model, &self.msg.from.as_ref().unwrap().mention_or_url())
+            .await;
+        count_submission(self.msg.chat.id.0).await;
+
+        self.bot
+            .send_message(self.msg.chat.id, t!("messages.thanks_for_send"))
+            .reply_markup(super::markups::get_thanks_markup(&model, model.location().is_some()))
             .await?;
 
-        model.update_msg_id(msg.id.0).await;
+        Ok(())
+    }
+
+    async fn add_to_album(&self, media_group_id: &str) -> anyhow::Result<()> {
+        let user = match &self.msg.from {
+            Some(u) => u.clone(),
+            None => {
+                error!("User is empty");
+                return Ok(());
+            }
+        };
+
+        if self.is_album_rejected(media_group_id).await? {
+            return Ok(());
+        }
+
+        let submission = match Submissions::get_or_create(user.id.0 as i64, media_group_id).await {
+            Some(s) => s,
+            None => {
+                error!("Submission not added");
+                return Ok(());
+            }
+        };
 
-        self.bot.send_message(self.msg.chat.id, t!("messages.thanks_for_send")).await?;
+        let mut model: photos::ActiveModel = self.msg.clone().into();
+        model.submission_uuid = Set(Some(submission.uuid));
+        model.position = Set(Some(self.msg.id.0));
+
+        if Photos::add(model).await.is_none() {
+            error!("Photo not added");
+            return Ok(());
+        }
+
+        // Every document restarts the timer: only the task of the last one sends the album.
+        // Files are inspected there as well, so updates of the chat aren't held by downloads
+        let key = format!("album_{}", submission.uuid);
+        let token = self.msg.id.0.to_string();
+
+        RedisManager::global()
+            .set_by_key_ex(&key, &token, ALBUM_COLLECT_DELAY.as_secs() * 10)
+            .await;
+
+        let bot = self.bot.clone();
+        let chat_id = self.msg.chat.id;
+
+        tokio::spawn(async move {
+            tokio::time::sleep(ALBUM_COLLECT_DELAY).await;
+
+            let redis = RedisManager::global();
+
+            if redis.get_by_key(&key).await.as_ref() != Some(&token) {
+                return;
+            }
+
+            if let Err(e) = inspect_album(&bot, chat_id, submission.uuid).await {
+                error!("Can't inspect album {}: {e}", submission.uuid);
+            }
+
+            // Another document has come while the files were inspected, its task sends the album
+            if redis.get_by_key(&key).await.as_ref() != Some(&token) {
+                return;
+            }
+
+            let photos = send_album_to_moderation(submission.uuid, &user).await;
+
+            // Buttons of any photo of the album apply to the whole album
+            let photo = match photos.first() {
+                Some(p) => p,
+                None => return,
+            };
+
+            count_submission(user.id.0 as i64).await;
+            let has_location = photos.iter().any(|p| p.location().is_some());
+            let request = bot
+                .send_message(chat_id, t!("messages.thanks_for_send"))
+                .reply_markup(super::markups::get_thanks_markup(photo, has_location));
+
+            if let Err(e) = request.await {
+                error!("Can't send message: {e}");
+            }
+        });
 
         Ok(())
     }
+
+    /// The album is a single submission, so limits are checked by its first document and the rest follow the decision
+    async fn is_album_rejected(&self, media_group_id: &str) -> anyhow::Result<bool> {
+        let redis = RedisManager::global();
+        let key = format!("album_limits_{media_group_id}");
+
+        if let Some(text) = redis.get_by_key(&key).await {
+            return Ok(!text.is_empty());
+        }
+
+        let text = check_limits(self.msg.chat.id.0).await.unwrap_or_default();
+
+        redis.set_by_key_ex(&key, &text, ALBUM_COLLECT_DELAY.as_secs() * 10).await;
+
+        if text.is_empty() {
+            return Ok(false);
+        }
+
+        self.bot.send_message(self.msg.chat.id, text).await?;
+
+        Ok(true)
+    }
+}
+
+/// Returns a message for the user when one of submission limits is reached.
+/// Nothing is counted here, only accepted submissions are counted by [`count_submission`]
+async fn check_limits(user_id: i64) -> Option<String> {
+    let user = Users::get(user_id).await;
+    let (hourly, pending) = BotManager::global().get_submission_limits(user.as_ref());
+
+    if pending > 0 && Photos::count_pending_by_user(user_id).await >= pending as u64 {
+        return Some(t!("messages.pending_limit_reached", limit = pending).to_string());
+    }
+
+    if hourly == 0 {
+        return None;
+    }
+
+    let redis = RedisManager::global();
+    let key = submissions_key(user_id);
+    let count: u64 = redis.get_by_key(&key).await.and_then(|c| c.parse().ok()).unwrap_or(0);
+
+    if count < hourly as u64 {
+        return None;
+    }
+
+    let minutes = redis.get_ttl(&key).await.unwrap_or(SUBMISSIONS_WINDOW).div_ceil(60);
+
+    Some(t!("messages.hourly_limit_reached", limit = hourly, minutes = minutes).to_string())
+}
+
+/// Counts a photo or an album which has got to moderators towards the hourly limit
+async fn count_submission(user_id: i64) {
+    RedisManager::global().increment_ex(&submissions_key(user_id), SUBMISSIONS_WINDOW).await;
+}
+
+fn submissions_key(user_id: i64) -> String {
+    format!("submissions_{user_id}")
+}
+
+/// Inspects pending photos of the album which haven't been inspected yet
+async fn inspect_album(bot: &Bot, chat_id: ChatId, submission_uuid: Uuid) -> anyhow::Result<()> {
+    let submission = match Submissions::get_by_id(submission_uuid).await {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    for photo in submission.photos().await {
+        if photo.is_pending() && photo.phash.is_none() {
+            check_photo(bot, chat_id, photo).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Stores what has been found in the file, rejects files which aren't supported images
+/// and exact repeats when it's enabled. Returns `None` when the photo has been rejected
+async fn check_photo(bot: &Bot, chat_id: ChatId, mut model: photos::Model) -> anyhow::Result<Option<photos::Model>> {
+    let manager = BotManager::global();
+
+    let inspection = manager.inspect_photo(&model).await;
+
+    if let Some(rejection) = &inspection.rejection {
+        let (reason, text) = match rejection {
+            Rejection::Unsupported => (t!("messages.unsupported_filetype_reason"), t!("messages.unsupported_filetype")),
+            Rejection::TooLarge { pixels } => (
+                t!("messages.image_too_large_reason"),
+                t!(
+                    "messages.image_too_large",
+                    megapixels = pixels / 1_000_000,
+                    max = manager.get_max_image_pixels() / 1_000_000
+                ),
+            ),
+            Rejection::Corrupt => (t!("messages.corrupt_file_reason"), t!("messages.corrupt_file")),
+            Rejection::Unchecked => (t!("messages.unchecked_file_reason"), t!("messages.unchecked_file")),
+        };
+
+        if model.decline(Some(reason.to_string())).await {
+            bot.send_message(chat_id, text).await?;
+        }
+
+        return Ok(None);
+    }
+
+    if model.set_inspection(&inspection).await {
+        model.mime_type = inspection.mime_type.clone().or(model.mime_type);
+        model.phash = inspection.phash;
+        model.latitude = inspection.location.map(|l| l.0);
+        model.longitude = inspection.location.map(|l| l.1);
+        model.private_metadata = (!inspection.private_metadata.is_empty()).then(|| inspection.private_metadata.join(", "));
+    }
+
+    if !manager.duplicate_auto_reject() {
+        return Ok(Some(model));
+    }
+
+    match manager.find_duplicate(&model).await {
+        Some((_, 0)) if model.decline(Some(t!("messages.duplicate_reject_reason").to_string())).await => {
+            bot.send_message(chat_id, t!("messages.duplicate_rejected")).await?;
+
+            Ok(None)
+        }
+        _ => Ok(Some(model)),
+    }
+}
+
+/// Sends cards for pending photos of the album which have none yet, documents coming late get their own cards
+async fn send_album_to_moderation(submission_uuid: Uuid, user: &User) -> Vec<photos::Model> {
+    let manager = BotManager::global();
+    let submission = match Submissions::get_by_id(submission_uuid).await {
+        Some(s) => s,
+        None => {
+            error!("Submission {submission_uuid} not found");
+            return Vec::new();
+        }
+    };
+    // Exact repeats and broken files could have been rejected already
+    let mut photos: Vec<photos::Model> = Vec::new();
+
+    for photo in submission.photos().await {
+        if photo.is_pending() && ModerationCards::get_by_photo(photo.uuid).await.is_empty() {
+            photos.push(photo);
+        }
+    }
+
+    if photos.len() < 2 {
+        for photo in &photos {
+            manager.send_photo_card(photo, &user.mention_or_url()).await;
+        }
+
+        return photos;
+    }
+
+    manager.send_album_card(&submission, &photos, &user.mention_or_url()).await;
+
+    photos
 }
 
 pub fn scheme() -> UpdateHandler<anyhow::Error> {
     dptree::entry().branch(
         Update::filter_message()
-            .enter_dialogue::<Message, RedisStorage<Json>, GlobalState>()
+            .chain(super::dialogue::enter())
             .filter(|m: Message| m.chat.is_private())
             .endpoint(MessageHandler::handle),
     )
@@ -1,7 +1,9 @@
+use backon::{ConstantBuilder, Retryable};
 use envconfig::Envconfig;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{path::Path, thread::sleep, time::Duration};
+use std::path::Path;
 use teloxide::{
     adaptors::DefaultParseMode,
     dispatching::{
@@ -11,10 +13,21 @@ use teloxide::{
     dptree,
     net::Download,
     prelude::*,
-    types::ParseMode,
+    types::{InputFile, InputMedia, InputMediaDocument, MessageId, ParseMode},
 };
 use tokio::fs::File;
 
+use crate::bot::types::{FileType, PhotoToUpload};
+use crate::db::entity::{
+    photos::{self, PhotoInspection, PhotoStatus, Rejection},
+    prelude::{ModerationCards, Moderators, Photos},
+    submissions, users,
+};
+use crate::geofence::Geofence;
+use crate::pool::ImagePool;
+use crate::template::{Template, Templates, Vars};
+use crate::types::CanMention;
+
 mod callback;
 mod command;
 mod dialogue;
@@ -26,6 +39,11 @@ pub(super) mod types;
 
 pub static INSTANCE: OnceCell<BotManager> = OnceCell::new();
 
+/// Limit of Telegram for captions of photos and documents
+const MAX_MEDIA_CAPTION_LENGTH: usize = 1024;
+
+static HASHTAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"#\w+").unwrap());
+
 pub type Bot = DefaultParseMode<teloxide::Bot>;
 pub type BotDialogue = Dialogue<GlobalState, RedisStorage<Json>>;
 
@@ -35,6 +53,7 @@ pub enum GlobalState {
     Idle,
     DeclinePhoto(dialogue::decline_photo::State),
     BanUser(dialogue::ban_user::State),
+    EditCaption(dialogue::edit_caption::State),
 }
 
 #[derive(Envconfig, Clone, Debug)]
@@ -43,6 +62,20 @@ pub struct BotConfig {
     pub group_id: i64,
     #[envconfig(from = "ADMIN_USER_ID")]
     pub admin_id: i64,
+    #[envconfig(from = "MODERATORS_CHAT_ID")]
+    pub moderators_chat_id: Option<i64>,
+    #[envconfig(from = "BAN_NOTIFY_USER", default = "true")]
+    pub notify_banned: bool,
+    #[envconfig(from = "SUBMISSIONS_PER_HOUR", default = "20")]
+    pub submissions_per_hour: u32,
+    #[envconfig(from = "MAX_PENDING_SUBMISSIONS", default = "30")]
+    pub max_pending_submissions: u32,
+    #[envconfig(from = "DUPLICATE_MAX_DISTANCE", default = "6")]
+    pub duplicate_max_distance: u32,
+    #[envconfig(from = "DUPLICATE_AUTO_REJECT", default = "false")]
+    pub duplicate_auto_reject: bool,
+    #[envconfig(from = "MAX_IMAGE_PIXELS", default = "120000000")]
+    pub max_image_pixels: u64,
     #[envconfig(from = "BOT_TOKEN")]
     pub bot_token: String,
 }
@@ -51,15 +84,27 @@ pub struct BotConfig {
 pub struct BotManager {
     bot: Bot,
     group_id: i64,
-    admin_id: i64,
+    moderators_chat_id: Option<i64>,
+    notify_banned: bool,
+    submissions_per_hour: u32,
+    max_pending_submissions: u32,
+    duplicate_max_distance: u32,
+    duplicate_auto_reject: bool,
+    max_image_pixels: u64,
 }
 
 impl BotManager {
     pub fn new(config: &BotConfig) -> Self {
         Self {
             bot: teloxide::Bot::new(&config.bot_token).parse_mode(ParseMode::Html),
-            admin_id: config.admin_id,
             group_id: config.group_id,
+            moderators_chat_id: config.moderators_chat_id,
+            notify_banned: config.notify_banned,
+            submissions_per_hour: config.submissions_per_hour,
+            max_pending_submissions: config.max_pending_submissions,
+            duplicate_max_distance: config.duplicate_max_distance,
+            duplicate_auto_reject: config.duplicate_auto_reject,
+            max_image_pixels: config.max_image_pixels,
         }
     }
 
@@ -67,14 +112,102 @@ impl BotManager {
         INSTANCE.get().expect("BotManager is not initialized")
     }
 
-    pub fn get_admin_id(&self) -> i64 {
-        self.admin_id
-    }
-
     pub fn get_group_id(&self) -> i64 {
         self.group_id
     }
 
+    pub fn notify_banned(&self) -> bool {
+        self.notify_banned
+    }
+
+    /// Returns hourly and pending limits of the user, zero means there is no limit
+    pub fn get_submission_limits(&self, user: Option<&users::Model>) -> (u32, u32) {
+        let hourly = user.and_then(|u| u.hourly_limit).map_or(self.submissions_per_hour, |l| l.max(0) as u32);
+        let pending = user
+            .and_then(|u| u.pending_limit)
+            .map_or(self.max_pending_submissions, |l| l.max(0) as u32);
+
+        (hourly, pending)
+    }
+
+    pub fn get_max_image_pixels(&self) -> u64 {
+        self.max_image_pixels
+    }
+
+    pub fn duplicate_auto_reject(&self) -> bool {
+        self.duplicate_auto_reject
+    }
+
+    /// Link to the post in the channel, works for private channels too
+    pub fn get_post_url(&self, msg_id: i64) -> String {
+        let group_id = self.group_id.to_string();
+        let channel_id = group_id.strip_prefix("-100").unwrap_or(&group_id);
+
+        format!("https://t.me/c/{channel_id}/{msg_id}")
+    }
+
+    /// Downloads the photo to validate it, compute its perceptual hash, read GPS coordinates and find private metadata.
+    /// Hash is stored as i64 because Postgres has no unsigned types.
+    /// A file which couldn't be checked is rejected as well, moderators only get validated files
+    pub async fn inspect_photo(&self, photo: &photos::Model) -> PhotoInspection {
+        let unchecked = PhotoInspection {
+            rejection: Some(Rejection::Unchecked),
+            ..Default::default()
+        };
+        let photo_to_upload = match PhotoToUpload::new(&FileType::from(&photo.mime_type)) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Can't create job directory for photo {}: {e}", photo.uuid);
+
+                return unchecked;
+            }
+        };
+
+        let download = || async { self.download_doc(&photo.file_id, photo_to_upload.document_path()).await };
+
+        if let Err(e) = download.retry(ConstantBuilder::default()).await {
+            error!("Can't download photo {}: {e}", photo.uuid);
+
+            return unchecked;
+        }
+
+        let max_image_pixels = self.max_image_pixels;
+
+        match ImagePool::global().run(move || inspect_file(photo_to_upload, max_image_pixels)).await {
+            Ok(inspection) => inspection,
+            Err(e) => {
+                error!("Can't inspect photo {}: {e}", photo.uuid);
+
+                unchecked
+            }
+        }
+    }
+
+    /// Closest earlier photo that looks the same, `None` when there is nothing similar
+    pub async fn find_duplicate(&self, photo: &photos::Model) -> Option<(photos::Model, u32)> {
+        Photos::find_similar(photo.uuid, photo.phash?, self.duplicate_max_distance).await
+    }
+
+    pub async fn get_duplicate_warning(&self, photo: &photos::Model) -> Option<String> {
+        let (duplicate, distance) = self.find_duplicate(photo).await?;
+
+        let warning = match (duplicate.channel_msg_id, duplicate.status) {
+            (Some(msg_id), _) => t!("messages.duplicate_posted", url = self.get_post_url(msg_id), distance = distance),
+            (None, PhotoStatus::Declined) => t!(
+                "messages.duplicate_declined",
+                reason = duplicate.decline_reason.unwrap_or_default(),
+                distance = distance
+            ),
+            _ => t!(
+                "messages.duplicate_sent",
+                date = duplicate.created_at.map(|d| d.format("%d.%m.%Y").to_string()).unwrap_or_default(),
+                distance = distance
+            ),
+        };
+
+        Some(warning.to_string())
+    }
+
     pub fn get_bot(&self) -> &Bot {
         &self.bot
     }
@@ -96,14 +229,345 @@ impl BotManager {
         .await
     }
 
-    pub async fn download_doc(&self, doc_id: &str, save_path: &Path) -> anyhow::Result<String> {
+    pub fn is_moderators_chat(&self, chat_id: ChatId) -> bool {
+        self.moderators_chat_id == Some(chat_id.0)
+    }
+
+    /// Cards go to the moderators group when it is configured, otherwise to every moderator personally
+    pub async fn get_moderation_chats(&self) -> Vec<ChatId> {
+        if let Some(chat_id) = self.moderators_chat_id {
+            return vec![ChatId(chat_id)];
+        }
+
+        Moderators::all().await.into_iter().map(|m| ChatId(m.user_id)).collect()
+    }
+
+    /// Caption of the channel post rendered from the `post` template
+    pub fn build_post_caption(photo: &photos::Model, mut vars: Vars, author: &str) -> String {
+        if photo.publish_location
+            && let Some((latitude, longitude)) = photo.location()
+        {
+            vars.insert("location", get_map_url(latitude, longitude));
+        }
+
+        vars.extend(get_caption_vars(photo, author));
+
+        render_fitting(Templates::global().post(), vars, photo, "")
+    }
+
+    /// Downloads the document only to read EXIF, so the preview matches the post
+    pub async fn get_post_caption_preview(&self, photo: &photos::Model) -> String {
+        let exif = match PhotoToUpload::new(&FileType::from(&photo.mime_type)) {
+            Ok(photo_to_upload) => match self.download_doc(&photo.file_id, photo_to_upload.document_path()).await {
+                Ok(_) => ImagePool::global()
+                    .run(move || photo_to_upload.get_exif_vars())
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Can't read EXIF of photo {} for preview: {e}", photo.uuid);
+                        Vars::new()
+                    }),
+                Err(e) => {
+                    warn!("Can't download photo {} for preview: {e}", photo.uuid);
+                    Vars::new()
+                }
+            },
+            Err(e) => {
+                warn!("Can't create job directory for photo {} preview: {e}", photo.uuid);
+                Vars::new()
+            }
+        };
+
+        Self::build_post_caption(photo, exif, &photo.user().await.mention_or_url())
+    }
+
+    /// Caption of the moderation card rendered from the `card` template, `footer` is appended to it
+    fn get_card_caption(&self, photo: &photos::Model, author: &str, footer: &str) -> String {
+        let mut vars = get_caption_vars(photo, author);
+
+        if let Some((latitude, longitude)) = photo.location() {
+            vars.insert("location", get_map_url(latitude, longitude));
+
+            if Geofence::global().contains(latitude, longitude) {
+                vars.insert("in_town", "yes".to_string());
+            }
+        }
+
+        if let Some(fields) = &photo.private_metadata {
+            vars.insert("private", fields.clone());
+        }
+
+        render_fitting(Templates::global().card(), vars, photo, footer)
+    }
+
+    pub async fn send_photo_card(&self, photo: &photos::Model, author: &str) {
+        let footer = match self.get_duplicate_warning(photo).await {
+            Some(warning) => format!("\n\n{warning}"),
+            None => String::new(),
+        };
+        let caption = self.get_card_caption(photo, author, &footer);
+
+        for chat_id in self.get_moderation_chats().await {
+            let res = self
+                .bot
+                .send_document(chat_id, InputFile::file_id(photo.file_id.clone().into()))
+                .caption(caption.clone())
+                .reply_markup(markups::get_document_markup(photo))
+                .await;
+
+            match res {
+                Ok(msg) => {
+                    ModerationCards::add_for_photo(photo.uuid, chat_id.0, msg.id.0).await;
+                }
+                Err(e) => error!("Can't send moderation card to {chat_id}: {e}"),
+            }
+        }
+    }
+
+    pub async fn send_album_card(&self, submission: &submissions::Model, photos: &[photos::Model], author: &str) {
+        let media: Vec<InputMedia> = photos
+            .iter()
+            .map(|p| {
+                let document = InputMediaDocument::new(InputFile::file_id(p.file_id.clone().into()));
+
+                InputMedia::Document(match p.caption_html() {
+                    Some(caption) => document.caption(format!("💬 {caption}")),
+                    None => document,
+                })
+            })
+            .collect();
+        let mut text = t!("messages.album_card", author = author, count = photos.len()).to_string();
+
+        for (i, photo) in photos.iter().enumerate() {
+            if let Some(warning) = self.get_duplicate_warning(photo).await {
+                text.push_str(&format!("\n\n{}. {warning}", i + 1));
+            }
+        }
+
+        for chat_id in self.get_moderation_chats().await {
+            let messages = match self.bot.send_media_group(chat_id, media.clone()).await {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("Can't send album to {chat_id}: {e}");
+                    continue;
+                }
+            };
+
+            for (photo, msg) in photos.iter().zip(messages.iter()) {
+                ModerationCards::add_for_photo(photo.uuid, chat_id.0, msg.id.0).await;
+            }
+
+            let res = self
+                .bot
+                .send_message(chat_id, text.clone())
+                .reply_markup(markups::get_album_markup(submission, photos, &[]))
+                .await;
+
+            match res {
+                Ok(msg) => {
+                    ModerationCards::add_for_submission(submission.uuid, chat_id.0, msg.id.0).await;
+                }
+                Err(e) => error!("Can't send album card to {chat_id}: {e}"),
+            }
+        }
+    }
+
+    /// Updates captions of the cards after the photo has been changed, buttons stay while the photo is pending.
+    /// Buttons of an album photo are on the album card, so that card gets the new state instead
+    pub async fn refresh_photo_cards(&self, photo: &photos::Model) {
+        let caption = self.get_card_caption(photo, &photo.user().await.mention_or_url(), "");
+
+        for card in ModerationCards::get_by_photo(photo.uuid).await {
+            let mut request = self
+                .bot
+                .edit_message_caption(ChatId(card.chat_id), MessageId(card.msg_id as i32))
+                .caption(caption.clone());
+
+            // Album documents are moderated by the separate album card
+            if photo.is_pending() && photo.submission_uuid.is_none() {
+                request = request.reply_markup(markups::get_document_markup(photo));
+            }
+
+            if let Err(e) = request.await {
+                warn!("Can't update moderation card {}: {e}", card.uuid);
+            }
+        }
+
+        if photo.is_pending()
+            && let Some(submission) = photo.submission().await
+        {
+            self.refresh_submission_cards(&submission).await;
+        }
+    }
+
+    /// Rebuilds buttons of the album cards, e.g. after a photo of the album has been changed
+    pub async fn refresh_submission_cards(&self, submission: &submissions::Model) {
+        let photos = callback::get_pending(submission).await;
+        let excluded = callback::get_excluded(submission).await;
+
+        for card in ModerationCards::get_by_submission(submission.uuid).await {
+            let res = self
+                .bot
+                .edit_message_reply_markup(ChatId(card.chat_id), MessageId(card.msg_id as i32))
+                .reply_markup(markups::get_album_markup(submission, &photos, &excluded))
+                .await;
+
+            if let Err(e) = res {
+                warn!("Can't update moderation card {}: {e}", card.uuid);
+            }
+        }
+    }
+
+    /// Replaces buttons on every card of the photo with the decision made by one of moderators
+    pub async fn resolve_photo_cards(&self, photo: &photos::Model, decision: &str) {
+        let caption = self.get_card_caption(photo, &photo.user().await.mention_or_url(), &format!("\n\n{decision}"));
+
+        for card in ModerationCards::get_by_photo(photo.uuid).await {
+            let res = self
+                .bot
+                .edit_message_caption(ChatId(card.chat_id), MessageId(card.msg_id as i32))
+                .caption(caption.clone())
+                .await;
+
+            if let Err(e) = res {
+                warn!("Can't update moderation card {}: {e}", card.uuid);
+            }
+        }
+    }
+
+    pub async fn resolve_submission_cards(&self, submission: &submissions::Model, decision: &str) {
+        let author = submission.user().await.mention_or_url();
+        let count = submission.photos().await.len();
+
+        for card in ModerationCards::get_by_submission(submission.uuid).await {
+            let res = self
+                .bot
+                .edit_message_text(
+                    ChatId(card.chat_id),
+                    MessageId(card.msg_id as i32),
+                    t!("messages.album_card_resolved", author = author, count = count, decision = decision),
+                )
+                .await;
+
+            if let Err(e) = res {
+                warn!("Can't update moderation card {}: {e}", card.uuid);
+            }
+        }
+    }
+
+    pub async fn download_doc(&self, doc_id: &str, save_path: &Path) -> anyhow::Result<()> {
         let doc = self.bot.get_file(doc_id.to_owned().into()).await?;
         let mut file = File::create(&save_path).await?;
 
         self.bot.download_file(&doc.path, &mut file).await?;
-        sleep(Duration::from_millis(50)); // Sometimes downloading is very fast
-        debug!("Filesize {} is = {}", save_path.to_str().unwrap(), std::fs::metadata(save_path)?.len());
+        // Readers open the file by path right after, so the data has to be on disk, not in the writer's buffer
+        file.sync_all().await?;
+        debug!("Filesize {} is = {}", save_path.to_string_lossy(), file.metadata().await?.len());
+
+        Ok(())
+    }
+}
+
+/// Values shared by post and card templates, `tags` are hashtags the caption contains
+fn get_caption_vars(photo: &photos::Model, author: &str) -> Vars {
+    let mut vars = Vars::from([("author", author.to_string())]);
+
+    if let Some(caption) = photo.caption_html() {
+        let mut tags: Vec<&str> = Vec::new();
+
+        for tag in HASHTAG.find_iter(&caption).map(|m| m.as_str()) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        vars.insert("tags", tags.join(" "));
+        vars.insert("caption", caption);
+    }
+
+    vars
+}
+
+/// Renders the template with the footer appended. Telegram rejects media with a caption longer than
+/// [`MAX_MEDIA_CAPTION_LENGTH`], so the author's text is shortened when the result doesn't fit
+fn render_fitting(template: &Template, mut vars: Vars, photo: &photos::Model, footer: &str) -> String {
+    let rendered = format!("{}{footer}", template.render(&vars));
+    let excess = visible_length(&rendered).saturating_sub(MAX_MEDIA_CAPTION_LENGTH);
+
+    let text = match photo.final_caption() {
+        _ if excess == 0 => return rendered,
+        Some(t) => t,
+        None => {
+            warn!("Caption of photo {} is too long even without the author's text", photo.uuid);
+
+            return rendered;
+        }
+    };
+    // One more character is taken by the ellipsis
+    let keep = text.chars().count().saturating_sub(excess + 1);
+    let short = format!("{}…", text.chars().take(keep).collect::<String>().trim_end());
+
+    vars.insert("caption", html_escape::encode_text(&short).to_string());
+
+    format!("{}{footer}", template.render(&vars))
+}
+
+/// Length of the text as Telegram counts it: without HTML tags and with entities as single characters
+fn visible_length(html: &str) -> usize {
+    let mut length = 0;
+    let mut in_tag = false;
+    let mut in_entity = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if in_tag => {}
+            '&' => {
+                in_entity = true;
+                length += 1;
+            }
+            ';' if in_entity => in_entity = false,
+            _ if in_entity => {}
+            _ => length += 1,
+        }
+    }
+
+    length
+}
+
+fn get_map_url(latitude: f64, longitude: f64) -> String {
+    format!("https://yandex.ru/maps/?pt={longitude:.6},{latitude:.6}&amp;z=17&amp;l=map")
+}
+
+/// Blocking part of [`BotManager::inspect_photo`], runs on the image pool
+fn inspect_file(mut photo_to_upload: PhotoToUpload, max_image_pixels: u64) -> PhotoInspection {
+    let mime_type = match photo_to_upload.detect_type() {
+        Some(t) => t.get_mime().to_string(),
+        None => {
+            return PhotoInspection {
+                rejection: Some(Rejection::Unsupported),
+                ..Default::default()
+            };
+        }
+    };
+
+    let location = photo_to_upload.get_gps();
+    let private_metadata = photo_to_upload.find_private_metadata();
+    let phash = match photo_to_upload.validate(max_image_pixels) {
+        Ok(img) => img.dhash() as i64,
+        Err(rejection) => {
+            return PhotoInspection {
+                rejection: Some(rejection),
+                ..Default::default()
+            };
+        }
+    };
 
-        Ok(save_path.to_str().unwrap().to_string())
+    PhotoInspection {
+        mime_type: Some(mime_type),
+        rejection: None,
+        phash: Some(phash),
+        location,
+        private_metadata,
     }
 }
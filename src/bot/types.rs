@@ -1,12 +1,17 @@
 use mime::Mime;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::db::entity::photos::Rejection;
+use crate::decoder::{self, DecodeError};
 use crate::exif::ExifLoader;
-use crate::image::Image;
+use crate::image::{Image, PhotoLimitError};
+use crate::metadata;
+use crate::template::Vars;
+use crate::watermark::Watermark;
+use crate::workspace::{JobDir, Workspace};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CallbackOperation {
@@ -16,6 +21,28 @@ pub enum CallbackOperation {
     Decline,
     #[serde(rename = "c")]
     Cancel,
+    #[serde(rename = "t")]
+    TogglePhoto,
+    #[serde(rename = "aa")]
+    ApproveAlbum,
+    #[serde(rename = "da")]
+    DeclineAlbum,
+    #[serde(rename = "bp")]
+    BansPage,
+    #[serde(rename = "ub")]
+    Unban,
+    #[serde(rename = "b")]
+    BanAuthor,
+    #[serde(rename = "w")]
+    Withdraw,
+    #[serde(rename = "ec")]
+    EditCaption,
+    #[serde(rename = "sc")]
+    SaveCaption,
+    #[serde(rename = "gl")]
+    ToggleLocation,
+    #[serde(rename = "wm")]
+    ToggleWatermark,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -24,29 +51,114 @@ pub struct CallbackData {
     pub operation: CallbackOperation,
     #[serde(rename = "doc", skip_serializing_if = "Option::is_none")]
     pub document: Option<Uuid>,
+    #[serde(rename = "p", default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u64>,
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<i64>,
 }
 
 impl CallbackData {
     pub fn new(operation: CallbackOperation) -> Self {
-        Self { operation, document: None }
+        Self {
+            operation,
+            document: None,
+            page: None,
+            user: None,
+        }
+    }
+
+    pub fn with_document(operation: CallbackOperation, document: Uuid) -> Self {
+        Self {
+            document: Some(document),
+            ..Self::new(operation)
+        }
+    }
+
+    pub fn with_page(operation: CallbackOperation, page: u64) -> Self {
+        Self {
+            page: Some(page),
+            ..Self::new(operation)
+        }
+    }
+
+    pub fn with_user(operation: CallbackOperation, user: i64) -> Self {
+        Self {
+            user: Some(user),
+            ..Self::new(operation)
+        }
     }
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum FileType {
     Heic,
+    Avif,
     Png,
     Jpeg,
+    Webp,
+    Tiff,
+    Dng,
 }
 
 impl FileType {
     pub fn get_extension(self) -> &'static str {
         match self {
             FileType::Heic => "heic",
+            FileType::Avif => "avif",
             FileType::Png => "png",
             FileType::Jpeg => "jpg",
+            FileType::Webp => "webp",
+            FileType::Tiff => "tiff",
+            FileType::Dng => "dng",
+        }
+    }
+
+    pub fn get_mime(self) -> &'static str {
+        match self {
+            FileType::Heic => "image/heic",
+            FileType::Avif => "image/avif",
+            FileType::Png => "image/png",
+            FileType::Jpeg => "image/jpeg",
+            FileType::Webp => "image/webp",
+            FileType::Tiff => "image/tiff",
+            FileType::Dng => "image/x-adobe-dng",
         }
     }
+
+    /// Only JPEG and PNG can be sent to Telegram as photos, the rest are published with a converted copy
+    pub fn needs_conversion(self) -> bool {
+        !matches!(self, FileType::Jpeg | FileType::Png)
+    }
+
+    /// Detects the type by the file signature, `None` means it's not an image we can publish
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xFF, 0xD8, 0xFF, ..] => Some(FileType::Jpeg),
+            [0x89, b'P', b'N', b'G', ..] => Some(FileType::Png),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(FileType::Webp),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] if decoder::is_dng(data) => Some(FileType::Dng),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(FileType::Tiff),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Self::detect_heif(data),
+            _ => None,
+        }
+    }
+
+    /// HEIC and AVIF share the container, they differ by brands listed in the `ftyp` box
+    fn detect_heif(data: &[u8]) -> Option<Self> {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let brands = data.get(8..size.min(data.len()))?;
+
+        // The minor version goes after the major brand and isn't a brand itself
+        brands
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .find_map(|(_, brand)| match brand {
+                b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" => Some(FileType::Heic),
+                b"avif" | b"avis" => Some(FileType::Avif),
+                _ => None,
+            })
+    }
 }
 
 impl From<String> for FileType {
@@ -60,7 +172,11 @@ impl From<&str> for FileType {
         match Mime::from_str(value) {
             Ok(t) => match t.subtype().as_str() {
                 "heic" | "heif" => FileType::Heic,
+                "avif" => FileType::Avif,
                 "png" => FileType::Png,
+                "webp" => FileType::Webp,
+                "tiff" => FileType::Tiff,
+                "x-adobe-dng" | "dng" => FileType::Dng,
                 _ => FileType::Jpeg,
             },
             Err(_) => FileType::Jpeg,
@@ -86,54 +202,115 @@ impl From<&Option<String>> for FileType {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Files of a single photo job, they live in a job directory which is removed when the value is dropped
+#[derive(Debug)]
 pub struct PhotoToUpload {
     file_type: FileType,
     doc_path: PathBuf,
     photo_path: PathBuf,
     jpeg_path: PathBuf,
     thumb_path: PathBuf,
+    // Re-encoded photo when the converted one breaks Telegram limits
+    upload_path: PathBuf,
+    reencoded: bool,
+    _dir: JobDir,
 }
 
 impl PhotoToUpload {
-    pub fn new(file_type: &FileType) -> Self {
+    pub fn new(file_type: &FileType) -> std::io::Result<Self> {
+        let dir = Workspace::global().job()?;
         let photo_path = match file_type {
-            FileType::Png => PathBuf::from(format!("/tmp/{}.{}", Uuid::new_v4(), FileType::Png.get_extension())),
-            _ => PathBuf::from(format!("/tmp/{}.{}", Uuid::new_v4(), FileType::Jpeg.get_extension())),
+            FileType::Png => dir.file(&format!("photo.{}", FileType::Png.get_extension())),
+            _ => dir.file(&format!("photo.{}", FileType::Jpeg.get_extension())),
         };
 
-        Self {
-            doc_path: PathBuf::from(format!("/tmp/{}.{}", Uuid::new_v4(), file_type.get_extension())),
+        Ok(Self {
+            doc_path: dir.file(&format!("original.{}", file_type.get_extension())),
             photo_path,
-            jpeg_path: PathBuf::from(format!("/tmp/{}.{}", Uuid::new_v4(), FileType::Jpeg.get_extension())),
-            thumb_path: PathBuf::from(format!("/tmp/{}.{}", Uuid::new_v4(), FileType::Jpeg.get_extension())),
+            jpeg_path: dir.file(&format!("converted.{}", FileType::Jpeg.get_extension())),
+            thumb_path: dir.file(&format!("thumb.{}", FileType::Jpeg.get_extension())),
+            upload_path: dir.file(&format!("upload.{}", FileType::Jpeg.get_extension())),
             file_type: *file_type,
+            reencoded: false,
+            _dir: dir,
+        })
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Checks what the downloaded document really is, a client may send a wrong MIME type.
+    /// Returns `None` when the file isn't an image we can publish
+    pub fn detect_type(&mut self) -> Option<FileType> {
+        let data = std::fs::read(&self.doc_path).ok()?;
+        let file_type = FileType::detect(&data)?;
+
+        if file_type != self.file_type {
+            self.photo_path.set_extension(match file_type {
+                FileType::Png => FileType::Png.get_extension(),
+                _ => FileType::Jpeg.get_extension(),
+            });
+            self.file_type = file_type;
         }
+
+        Some(file_type)
     }
 
-    pub fn get_exif_info(&self) -> Vec<String> {
-        let mut messages: Vec<String> = Vec::with_capacity(5);
+    /// EXIF values for caption templates, missing values are left out
+    pub fn get_exif_vars(&self) -> Vars {
+        let mut vars = Vars::new();
 
         if let Ok(exif_info) = ExifLoader::new(&self.doc_path) {
-            if let Some(maker_model) = exif_info.get_maker_model() {
-                messages.push(format!("📸 Снято на: {maker_model}"))
+            let values = [
+                ("camera", exif_info.get_maker_model()),
+                ("lens", exif_info.get_lens_model()),
+                ("settings", exif_info.get_photo_info_string()),
+                ("flash", exif_info.is_flash_fired().then(|| "yes".to_string())),
+                ("date", exif_info.get_date_time()),
+                ("software", exif_info.get_software()),
+            ];
+
+            // Values come from the file, so they must not break HTML of the caption
+            for (name, value) in values {
+                if let Some(v) = value {
+                    vars.insert(name, html_escape::encode_text(&v).to_string());
+                }
             }
+        };
 
-            if let Some(afp_info) = exif_info.get_photo_info_string() {
-                messages.push(format!("ℹ️ {afp_info}"))
-            }
+        vars
+    }
 
-            if let Some(_software) = exif_info.get_software() {
-                // messages.push(format!("⚠️ Обработано в: {}", software))
+    pub fn get_gps(&self) -> Option<(f64, f64)> {
+        ExifLoader::new(&self.doc_path).ok()?.get_gps()
+    }
+
+    /// Removes private metadata from every file which is going to be published, returns names of removed fields.
+    /// Decoders copy EXIF into the photo, and it's published as is when it doesn't have to be re-encoded
+    pub fn sanitize(&self, keep_gps: bool) -> anyhow::Result<Vec<String>> {
+        let mut removed: Vec<String> = Vec::new();
+
+        for path in [&self.doc_path, &self.photo_path, &self.jpeg_path] {
+            if !path.exists() {
+                continue;
             }
 
-            // Add delimiter
-            if !messages.is_empty() {
-                messages.push(String::new());
+            for field in metadata::sanitize(path, keep_gps)? {
+                if !removed.contains(&field) {
+                    removed.push(field);
+                }
             }
-        };
+        }
+
+        Ok(removed)
+    }
 
-        messages
+    pub fn find_private_metadata(&self) -> Vec<String> {
+        metadata::find_private(&self.doc_path).unwrap_or_else(|e| {
+            warn!("Can't read metadata of {}: {e}", self.doc_path.to_string_lossy());
+            Vec::new()
+        })
     }
 
     pub fn convert(&self) -> Result<(), BotError> {
@@ -141,69 +318,97 @@ impl PhotoToUpload {
             return Err(BotError::FileNotExists(format!("File {} not exists!", self.doc_path.to_string_lossy())));
         }
 
-        if self.file_type == FileType::Heic {
-            let out = Command::new("heif-convert")
-                .args(["-q", "90"])
-                .arg(&self.doc_path)
-                .arg(&self.photo_path)
-                .output();
+        let copy = |from: &Path, to: &Path| {
+            std::fs::copy(from, to)
+                .map(|_| ())
+                .map_err(|e| BotError::CopyFailed(format!("Can't copy {} to {}: {e}", from.to_string_lossy(), to.to_string_lossy())))
+        };
 
-            if let Ok(output) = out {
-                if !output.status.success() {
-                    error!("Convert failed: {output:?}");
+        let decoded = match self.file_type {
+            FileType::Jpeg | FileType::Png => {
+                copy(&self.doc_path, &self.photo_path)?;
 
-                    return Err(BotError::ConvertingFailed(format!("Converting failed: {}", output.status)));
-                }
+                return copy(&self.doc_path, &self.jpeg_path);
+            }
+            FileType::Heic | FileType::Avif => decoder::decode_heif(&self.doc_path, &self.photo_path),
+            FileType::Webp | FileType::Tiff => decoder::decode_image(&self.doc_path, &self.photo_path),
+            FileType::Dng => decoder::decode_dng(&self.doc_path, &self.photo_path),
+        };
 
-                debug!("{output:?}");
+        decoded.map_err(BotError::DecodingFailed)?;
 
-                std::fs::copy(&self.photo_path, &self.jpeg_path).unwrap_or_default();
+        copy(&self.photo_path, &self.jpeg_path)
+    }
+
+    /// Makes sure the document is an image which can be published. Sizes are read from headers first,
+    /// so decompression bombs are refused before they are decoded, then the converted photo is decoded completely
+    pub fn validate(&self, max_pixels: u64) -> Result<Image, Rejection> {
+        let check_size = |path: &Path, size: Result<(u32, u32), DecodeError>| match size {
+            Ok((width, height)) if width as u64 * height as u64 > max_pixels => Err(Rejection::TooLarge {
+                pixels: width as u64 * height as u64,
+            }),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("Can't read size of {}: {e}", path.to_string_lossy());
+                Err(Rejection::Corrupt)
             }
+        };
 
-            return Ok(());
+        match self.file_type {
+            FileType::Heic | FileType::Avif => check_size(&self.doc_path, decoder::read_heif_dimensions(&self.doc_path))?,
+            // Raw data is never decoded, only the embedded preview which is checked after conversion
+            FileType::Dng => {}
+            _ => check_size(&self.doc_path, decoder::read_dimensions(&self.doc_path))?,
         }
 
-        std::fs::copy(&self.doc_path, &self.photo_path).unwrap_or_default();
-        std::fs::copy(&self.doc_path, &self.jpeg_path).unwrap_or_default();
+        if let Err(e) = self.convert() {
+            warn!("Can't convert {}: {e:?}", self.doc_path.to_string_lossy());
 
-        Ok(())
+            return Err(Rejection::Corrupt);
+        }
+
+        check_size(&self.photo_path, decoder::read_dimensions(&self.photo_path))?;
+
+        Image::try_new(&self.photo_path).ok_or(Rejection::Corrupt)
     }
 
-    pub fn check(&self) -> Result<(), BotError> {
-        let mut img = Image::new(&self.photo_path);
-        let file_metadata = std::fs::metadata(&self.photo_path);
+    /// Makes sure the photo can be sent with `send_photo`, it's re-encoded when it breaks Telegram limits
+    /// or has to be rotated, since orientation is applied to pixels and isn't written back to EXIF.
+    /// The watermark with the author's signature goes only to this copy, documents keep the original
+    pub fn check(&mut self, signature: Option<&str>) -> Result<(), BotError> {
+        let mut img = match Image::try_new(&self.photo_path) {
+            Some(i) => i,
+            None => {
+                return Err(BotError::DecodingFailed(DecodeError::NoOutput(
+                    self.photo_path.to_string_lossy().to_string(),
+                )));
+            }
+        };
 
-        if let Err(e) = file_metadata {
-            return Err(BotError::GetMetadataFailed(format!("Get metadata failed: {e}")));
-        }
+        let file_size = match std::fs::metadata(&self.photo_path) {
+            Ok(m) => m.len(),
+            Err(e) => return Err(BotError::GetMetadataFailed(format!("Get metadata failed: {e}"))),
+        };
 
-        if file_metadata.unwrap().len() > 10 * 1024 * 1024 {
-            info!("Photo is over 10 MB. Scailing on 0.5x");
+        let watermarked = signature.is_some_and(|author| Watermark::global().apply(&mut img, author));
 
-            if !img.scale(0.5).save(&self.photo_path) {
-                error!("Scaling failed!");
-            }
+        if !watermarked && !img.is_reoriented() && img.fits_photo_limits(file_size) {
+            return Ok(());
         }
 
-        let (width, height) = img.get_size();
+        let data = img.encode_for_photo_limits().map_err(BotError::PhotoLimitsExceeded)?;
 
-        if width > 4000 || height > 4000 {
-            let scale = img.get_scaling(4000);
+        info!("Photo has been re-encoded from {file_size} to {} bytes", data.len());
 
-            info!("Photo is over 4000 px. Scailing to {scale}x");
-
-            if !img.scale(scale).save(&self.photo_path) {
-                error!("Scaling failed!");
-            }
-        }
+        std::fs::write(&self.upload_path, data).map_err(|e| BotError::FileNotExists(format!("Can't write photo: {e}")))?;
+        self.reencoded = true;
 
         Ok(())
     }
 
+    /// Photo for `send_photo`, [`Self::check`] has to be passed before
     pub fn photo(&self) -> &Path {
-        let _ = self.check();
-
-        &self.photo_path
+        if self.reencoded { &self.upload_path } else { &self.photo_path }
     }
 
     pub fn converted(&self) -> &Path {
@@ -214,31 +419,18 @@ impl PhotoToUpload {
         &self.doc_path
     }
 
-    pub fn thumbnail(&self) -> &Path {
-        let mut img = Image::new(&self.photo_path);
-        img.resize(320).save(&self.thumb_path);
-
-        &self.thumb_path
-    }
-
-    pub fn delete_all(&self) -> bool {
-        if std::fs::remove_file(&self.doc_path).is_err() {
-            return false;
-        }
-
-        if std::fs::remove_file(&self.photo_path).is_err() {
-            return false;
-        }
-
-        if std::fs::remove_file(&self.jpeg_path).is_err() {
-            return false;
-        }
-
-        if std::fs::remove_file(&self.thumb_path).is_err() {
-            return false;
+    pub fn make_thumbnail(&self) {
+        match Image::try_new(&self.photo_path) {
+            Some(mut img) => {
+                img.resize(320).save(&self.thumb_path);
+            }
+            None => error!("Can't open {} to make a thumbnail", self.photo_path.to_string_lossy()),
         }
+    }
 
-        true
+    /// Thumbnail for documents, [`Self::make_thumbnail`] has to be called before
+    pub fn thumbnail(&self) -> &Path {
+        &self.thumb_path
     }
 }
 
@@ -246,6 +438,8 @@ impl PhotoToUpload {
 #[derive(Debug)]
 pub enum BotError {
     FileNotExists(String),
+    CopyFailed(String),
     GetMetadataFailed(String),
-    ConvertingFailed(String),
+    DecodingFailed(DecodeError),
+    PhotoLimitsExceeded(PhotoLimitError),
 }
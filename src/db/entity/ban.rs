@@ -1,6 +1,11 @@
 use crate::db::Database;
-use sea_orm::Set;
+use crate::redis::RedisManager;
+use chrono::Utc;
 use sea_orm::entity::prelude::*;
+use sea_orm::{Condition, QueryOrder, Set};
+
+// Ban status is checked on every incoming message, so it is cached for a while
+const BAN_CACHE_TTL: u64 = 60;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
 #[sea_orm(table_name = "ban")]
@@ -10,6 +15,8 @@ pub struct Model {
     pub user_id: i64,
     pub reason: Option<String>,
     pub banned_at: Option<DateTime>,
+    pub expires_at: Option<DateTime>,
+    pub banned_by: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -33,23 +40,88 @@ impl Related<super::users::Entity> for Entity {
 impl ActiveModelBehavior for ActiveModel {}
 
 impl Entity {
-    pub async fn user(user_id: i64, reason: &str) -> bool {
-        Entity::insert(ActiveModel {
+    pub async fn user(user_id: i64, reason: &str, expires_at: Option<DateTime>, banned_by: Option<i64>) -> bool {
+        let res = Entity::insert(ActiveModel {
             user_id: Set(user_id),
             reason: Set(Some(reason.to_string())),
+            expires_at: Set(expires_at),
+            banned_by: Set(banned_by),
             ..Default::default()
         })
         .exec(Database::global().connection())
-        .await
-        .is_ok()
+        .await;
+
+        Self::forget(user_id).await;
+
+        res.is_ok()
+    }
+
+    pub async fn unban(user_id: i64) -> bool {
+        let res = Entity::delete_many()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Self::active_condition())
+            .exec(Database::global().connection())
+            .await;
+
+        Self::forget(user_id).await;
+
+        res.is_ok_and(|r| r.rows_affected > 0)
     }
 
     pub async fn exists(user_id: i64) -> bool {
-        Entity::find()
+        let redis = RedisManager::global();
+        let key = Self::cache_key(user_id);
+
+        if let Some(cached) = redis.get_by_key(&key).await {
+            return cached == "1";
+        }
+
+        let banned = Self::get_active(user_id).await.is_some();
+
+        redis.set_by_key_ex(&key, if banned { "1" } else { "0" }, BAN_CACHE_TTL).await;
+
+        banned
+    }
+
+    pub async fn get_active(user_id: i64) -> Option<Model> {
+        let res = Entity::find()
             .filter(Column::UserId.eq(user_id))
-            .count(Database::global().connection())
-            .await
-            .unwrap_or(0)
-            > 0
+            .filter(Self::active_condition())
+            .one(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get ban from database: {e}");
+            None
+        })
+    }
+
+    pub async fn get_active_page(page: u64, per_page: u64) -> (Vec<Model>, u64) {
+        let paginator = Entity::find()
+            .filter(Self::active_condition())
+            .order_by_desc(Column::BannedAt)
+            .paginate(Database::global().connection(), per_page);
+
+        let pages = paginator.num_pages().await.unwrap_or(0);
+        let bans = paginator.fetch_page(page).await.unwrap_or_else(|e| {
+            error!("Can't get bans from database: {e}");
+            Vec::new()
+        });
+
+        (bans, pages)
+    }
+
+    fn active_condition() -> Condition {
+        Condition::any()
+            .add(Column::ExpiresAt.is_null())
+            .add(Column::ExpiresAt.gt(Utc::now().naive_utc()))
+    }
+
+    fn cache_key(user_id: i64) -> String {
+        format!("ban_{user_id}")
+    }
+
+    async fn forget(user_id: i64) {
+        RedisManager::global().delete_by_key(&Self::cache_key(user_id)).await;
     }
 }
@@ -1,6 +1,10 @@
 pub mod prelude;
 
 pub mod ban;
+pub mod moderation_cards;
+pub mod moderation_events;
+pub mod moderators;
 pub mod photos;
 pub mod reactions;
+pub mod submissions;
 pub mod users;
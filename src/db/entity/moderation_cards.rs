@@ -0,0 +1,112 @@
+use crate::db::Database;
+use sea_orm::Set;
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "moderation_cards")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub uuid: Uuid,
+    pub photo_uuid: Option<Uuid>,
+    pub submission_uuid: Option<Uuid>,
+    pub chat_id: i64,
+    pub msg_id: i64,
+    pub created_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::photos::Entity",
+        from = "Column::PhotoUuid",
+        to = "super::photos::Column::Uuid",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Photos,
+    #[sea_orm(
+        belongs_to = "super::submissions::Entity",
+        from = "Column::SubmissionUuid",
+        to = "super::submissions::Column::Uuid",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Submissions,
+}
+
+impl Related<super::photos::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Photos.def()
+    }
+}
+
+impl Related<super::submissions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Submissions.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Entity {
+    pub async fn add_for_photo(photo_uuid: Uuid, chat_id: i64, msg_id: i32) -> bool {
+        Self::insert(ActiveModel {
+            photo_uuid: Set(Some(photo_uuid)),
+            chat_id: Set(chat_id),
+            msg_id: Set(msg_id as i64),
+            ..Default::default()
+        })
+        .exec(Database::global().connection())
+        .await
+        .is_ok()
+    }
+
+    pub async fn add_for_submission(submission_uuid: Uuid, chat_id: i64, msg_id: i32) -> bool {
+        Self::insert(ActiveModel {
+            submission_uuid: Set(Some(submission_uuid)),
+            chat_id: Set(chat_id),
+            msg_id: Set(msg_id as i64),
+            ..Default::default()
+        })
+        .exec(Database::global().connection())
+        .await
+        .is_ok()
+    }
+
+    pub async fn get_by_photo(photo_uuid: Uuid) -> Vec<Model> {
+        let res = Self::find()
+            .filter(Column::PhotoUuid.eq(photo_uuid))
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderation cards from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn get_by_submission(submission_uuid: Uuid) -> Vec<Model> {
+        let res = Self::find()
+            .filter(Column::SubmissionUuid.eq(submission_uuid))
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderation cards from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn get_by_msg(chat_id: i64, msg_id: i32) -> Option<Model> {
+        let res = Self::find()
+            .filter(Column::ChatId.eq(chat_id))
+            .filter(Column::MsgId.eq(msg_id))
+            .one(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderation card from database: {e}");
+            None
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use crate::db::Database;
+use sea_orm::entity::prelude::*;
+use sea_orm::{QueryOrder, Set};
+
+#[derive(EnumIter, DeriveActiveEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "ModerationAction")]
+pub enum ModerationAction {
+    #[sea_orm(string_value = "Approve")]
+    Approve,
+    #[sea_orm(string_value = "Decline")]
+    Decline,
+    #[sea_orm(string_value = "Ban")]
+    Ban,
+    #[sea_orm(string_value = "Unban")]
+    Unban,
+    #[sea_orm(string_value = "Requeue")]
+    Requeue,
+}
+
+impl ModerationAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModerationAction::Approve => "approve",
+            ModerationAction::Decline => "decline",
+            ModerationAction::Ban => "ban",
+            ModerationAction::Unban => "unban",
+            ModerationAction::Requeue => "requeue",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "moderation_events")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub uuid: Uuid,
+    pub photo_uuid: Option<Uuid>,
+    pub moderator_id: i64,
+    pub action: ModerationAction,
+    pub reason: Option<String>,
+    pub created_at: Option<DateTime>,
+    /// User the action is about, set for bans and unbans
+    pub user_id: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::photos::Entity",
+        from = "Column::PhotoUuid",
+        to = "super::photos::Column::Uuid",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Photos,
+}
+
+impl Related<super::photos::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Photos.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Entity {
+    pub async fn add(photo_uuid: Option<Uuid>, moderator_id: i64, action: ModerationAction, reason: Option<String>) -> bool {
+        Self::add_event(ActiveModel {
+            photo_uuid: Set(photo_uuid),
+            moderator_id: Set(moderator_id),
+            action: Set(action),
+            reason: Set(reason),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Adds an event about the user rather than about a photo, like a ban
+    pub async fn add_for_user(user_id: i64, photo_uuid: Option<Uuid>, moderator_id: i64, action: ModerationAction, reason: Option<String>) -> bool {
+        Self::add_event(ActiveModel {
+            user_id: Set(Some(user_id)),
+            photo_uuid: Set(photo_uuid),
+            moderator_id: Set(moderator_id),
+            action: Set(action),
+            reason: Set(reason),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn add_event(model: ActiveModel) -> bool {
+        let res = Self::insert(model).exec(Database::global().connection()).await;
+
+        match res {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Can't add moderation event to database: {e}");
+                false
+            }
+        }
+    }
+
+    pub async fn get_by_photo(photo_uuid: Uuid) -> Vec<Model> {
+        let res = Self::find()
+            .filter(Column::PhotoUuid.eq(photo_uuid))
+            .order_by_asc(Column::CreatedAt)
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderation events from database: {e}");
+            Vec::new()
+        })
+    }
+}
@@ -0,0 +1,114 @@
+use crate::db::Database;
+use sea_orm::entity::prelude::*;
+use sea_orm::{QueryOrder, Set, sea_query::OnConflict};
+
+#[derive(EnumIter, DeriveActiveEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "ModeratorRole")]
+pub enum ModeratorRole {
+    #[sea_orm(string_value = "Owner")]
+    Owner,
+    #[sea_orm(string_value = "Moderator")]
+    Moderator,
+    #[sea_orm(string_value = "Viewer")]
+    Viewer,
+}
+
+impl ModeratorRole {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModeratorRole::Owner => "owner",
+            ModeratorRole::Moderator => "moderator",
+            ModeratorRole::Viewer => "viewer",
+        }
+    }
+}
+
+impl TryFrom<&str> for ModeratorRole {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "owner" => Ok(ModeratorRole::Owner),
+            "moderator" | "mod" => Ok(ModeratorRole::Moderator),
+            "viewer" => Ok(ModeratorRole::Viewer),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "moderators")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: i64,
+    pub role: ModeratorRole,
+    pub added_by: Option<i64>,
+    pub created_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Entity {
+    pub async fn get(user_id: i64) -> Option<Model> {
+        let res = Self::find_by_id(user_id).one(Database::global().connection()).await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderator from database: {e}");
+            None
+        })
+    }
+
+    pub async fn all() -> Vec<Model> {
+        let res = Self::find().order_by_asc(Column::CreatedAt).all(Database::global().connection()).await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get moderators from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn set(user_id: i64, role: ModeratorRole, added_by: Option<i64>) -> bool {
+        Self::insert(ActiveModel {
+            user_id: Set(user_id),
+            role: Set(role),
+            added_by: Set(added_by),
+            ..Default::default()
+        })
+        .on_conflict(OnConflict::column(Column::UserId).update_column(Column::Role).to_owned())
+        .exec(Database::global().connection())
+        .await
+        .is_ok()
+    }
+
+    pub async fn remove(user_id: i64) -> bool {
+        Self::delete_by_id(user_id)
+            .exec(Database::global().connection())
+            .await
+            .map(|r| r.rows_affected > 0)
+            .unwrap_or(false)
+    }
+
+    pub async fn ensure_owner(user_id: i64) -> bool {
+        match Self::get(user_id).await {
+            Some(m) if m.role == ModeratorRole::Owner => true,
+            _ => Self::set(user_id, ModeratorRole::Owner, None).await,
+        }
+    }
+
+    pub async fn can_moderate(user_id: i64) -> bool {
+        Self::get(user_id).await.is_some_and(|m| m.can_moderate())
+    }
+
+    pub async fn is_owner(user_id: i64) -> bool {
+        Self::get(user_id).await.is_some_and(|m| m.role == ModeratorRole::Owner)
+    }
+}
+
+impl Model {
+    pub fn can_moderate(&self) -> bool {
+        self.role != ModeratorRole::Viewer
+    }
+}
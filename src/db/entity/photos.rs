@@ -1,9 +1,63 @@
 use crate::db::{Database, entity::prelude::Reactions};
 use chrono::Utc;
 use sea_orm::entity::prelude::*;
-use sea_orm::{IntoActiveModel, Set};
+use sea_orm::{NotSet, QueryOrder, QuerySelect, Set};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+/// Longest caption kept for a post, both the author's one and the moderator's override
+pub const MAX_CAPTION_LENGTH: usize = 512;
+
+#[derive(EnumIter, DeriveActiveEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "PhotoStatus")]
+pub enum PhotoStatus {
+    #[sea_orm(string_value = "Pending")]
+    Pending,
+    #[sea_orm(string_value = "Approved")]
+    Approved,
+    #[sea_orm(string_value = "Declined")]
+    Declined,
+    #[sea_orm(string_value = "Withdrawn")]
+    Withdrawn,
+    #[sea_orm(string_value = "Failed")]
+    Failed,
+}
+
+impl PhotoStatus {
+    pub fn can_become(self, next: PhotoStatus) -> bool {
+        matches!(
+            (self, next),
+            (
+                PhotoStatus::Pending,
+                PhotoStatus::Approved | PhotoStatus::Declined | PhotoStatus::Withdrawn
+            ) | (PhotoStatus::Approved, PhotoStatus::Failed)
+                | (PhotoStatus::Failed, PhotoStatus::Approved)
+        )
+    }
+}
+
+/// Why the file has been refused before it got to moderators
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rejection {
+    Unsupported,
+    TooLarge {
+        pixels: u64,
+    },
+    Corrupt,
+    /// The file couldn't be downloaded or processed, so nothing is known about it
+    Unchecked,
+}
+
+/// What has been found in the file when it was sent
+#[derive(Clone, Debug, Default)]
+pub struct PhotoInspection {
+    pub mime_type: Option<String>,
+    /// Set when the file has been downloaded but can't be published
+    pub rejection: Option<Rejection>,
+    pub phash: Option<i64>,
+    pub location: Option<(f64, f64)>,
+    pub private_metadata: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "photos")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -12,10 +66,22 @@ pub struct Model {
     pub msg_id: Option<i64>,
     pub file_id: String,
     pub mime_type: Option<String>,
-    pub is_approved: bool,
+    pub status: PhotoStatus,
+    pub decline_reason: Option<String>,
     pub channel_msg_id: Option<i64>,
     pub created_at: Option<DateTime>,
     pub posted_at: Option<DateTime>,
+    pub queued_at: Option<DateTime>,
+    pub submission_uuid: Option<Uuid>,
+    pub position: Option<i32>,
+    pub phash: Option<i64>,
+    pub caption: Option<String>,
+    pub caption_override: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub publish_location: bool,
+    pub private_metadata: Option<String>,
+    pub watermark: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -28,6 +94,14 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     Users,
+    #[sea_orm(
+        belongs_to = "super::submissions::Entity",
+        from = "Column::SubmissionUuid",
+        to = "super::submissions::Column::Uuid",
+        on_update = "NoAction",
+        on_delete = "SetNull"
+    )]
+    Submissions,
 }
 
 impl Related<super::users::Entity> for Entity {
@@ -36,6 +110,12 @@ impl Related<super::users::Entity> for Entity {
     }
 }
 
+impl Related<super::submissions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Submissions.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 impl Entity {
@@ -60,8 +140,11 @@ impl Entity {
         })
     }
 
-    pub async fn get_by_msg_id(msg_id: i32) -> Option<Model> {
-        let res = Self::find().filter(Column::MsgId.eq(msg_id)).one(Database::global().connection()).await;
+    pub async fn get_by_channel_msg_id(msg_id: i32) -> Option<Model> {
+        let res = Self::find()
+            .filter(Column::ChannelMsgId.eq(msg_id))
+            .one(Database::global().connection())
+            .await;
 
         res.unwrap_or_else(|e| {
             error!("Can't get photo from database: {e}");
@@ -69,9 +152,11 @@ impl Entity {
         })
     }
 
-    pub async fn get_by_channel_msg_id(msg_id: i32) -> Option<Model> {
+    pub async fn get_next_ready() -> Option<Model> {
         let res = Self::find()
-            .filter(Column::ChannelMsgId.eq(msg_id))
+            .filter(Column::Status.eq(PhotoStatus::Approved))
+            .filter(Column::PostedAt.is_null())
+            .order_by_asc(Column::QueuedAt)
             .one(Database::global().connection())
             .await;
 
@@ -80,23 +165,241 @@ impl Entity {
             None
         })
     }
+
+    pub async fn get_by_user(user_id: i64, limit: u64) -> Vec<Model> {
+        let res = Self::find()
+            .filter(Column::UserId.eq(user_id))
+            .order_by_desc(Column::CreatedAt)
+            .limit(limit)
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get user photos from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn get_pending_by_user(user_id: i64) -> Vec<Model> {
+        let res = Self::find()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Status.eq(PhotoStatus::Pending))
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get pending photos from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn count_pending_by_user(user_id: i64) -> u64 {
+        Self::find()
+            .filter(Column::UserId.eq(user_id))
+            .filter(Column::Status.eq(PhotoStatus::Pending))
+            .count(Database::global().connection())
+            .await
+            .unwrap_or(0)
+    }
+
+    /// Returns the closest earlier photo by perceptual hash with its Hamming distance.
+    /// The distance is computed by the database, so only the matching photo is loaded
+    pub async fn find_similar(uuid: Uuid, phash: i64, max_distance: u32) -> Option<(Model, u32)> {
+        let distance = Expr::cust_with_values(r#"bit_count(("phash" # $1)::bit(64))"#, [phash]);
+        let res = Self::find()
+            .filter(Column::Phash.is_not_null())
+            .filter(Column::Uuid.ne(uuid))
+            .filter(Column::Status.ne(PhotoStatus::Withdrawn))
+            .filter(Expr::expr(distance.clone()).lte(max_distance as i64))
+            .order_by_asc(distance)
+            .order_by_asc(Column::CreatedAt)
+            .one(Database::global().connection())
+            .await;
+
+        let photo = res.unwrap_or_else(|e| {
+            error!("Can't get photos from database: {e}");
+            None
+        })?;
+        let distance = (photo.phash? ^ phash).count_ones();
+
+        Some((photo, distance))
+    }
+
+    pub async fn count_ready() -> u64 {
+        Self::find()
+            .filter(Column::Status.eq(PhotoStatus::Approved))
+            .filter(Column::PostedAt.is_null())
+            .count(Database::global().connection())
+            .await
+            .unwrap_or(0)
+    }
 }
 
 impl Model {
-    pub async fn approve(&self, msg_id: i32) -> bool {
-        let mut model = self.clone().into_active_model();
-        model.is_approved = Set(true);
-        model.posted_at = Set(Some(Utc::now().naive_utc()));
-        model.channel_msg_id = Set(Some(msg_id as i64));
+    pub fn is_pending(&self) -> bool {
+        self.status == PhotoStatus::Pending
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status == PhotoStatus::Approved && self.posted_at.is_none()
+    }
+
+    /// Caption can be changed until the photo is posted
+    pub fn is_editable(&self) -> bool {
+        self.is_pending() || self.is_ready()
+    }
+
+    pub fn is_posted(&self) -> bool {
+        self.status == PhotoStatus::Approved && self.posted_at.is_some()
+    }
+
+    pub async fn approve(&self) -> bool {
+        self.transition(
+            PhotoStatus::Approved,
+            ActiveModel {
+                queued_at: Set(Some(Utc::now().naive_utc())),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn decline(&self, reason: Option<String>) -> bool {
+        self.transition(
+            PhotoStatus::Declined,
+            ActiveModel {
+                decline_reason: Set(reason),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn withdraw(&self) -> bool {
+        self.transition(PhotoStatus::Withdrawn, ActiveModel { ..Default::default() }).await
+    }
+
+    pub async fn fail(&self) -> bool {
+        self.transition(PhotoStatus::Failed, ActiveModel { ..Default::default() }).await
+    }
+
+    /// Puts a photo which couldn't be posted to the end of the queue
+    pub async fn requeue(&self) -> bool {
+        self.status == PhotoStatus::Failed
+            && self
+                .transition(
+                    PhotoStatus::Approved,
+                    ActiveModel {
+                        queued_at: Set(Some(Utc::now().naive_utc())),
+                        ..Default::default()
+                    },
+                )
+                .await
+    }
+
+    pub async fn mark_posted(&self, msg_id: i32) -> bool {
+        if !self.is_ready() {
+            warn!("Photo {} can't be marked as posted in status {:?}", self.uuid, self.status);
+            return false;
+        }
+
+        Entity::update_many()
+            .set(ActiveModel {
+                posted_at: Set(Some(Utc::now().naive_utc())),
+                channel_msg_id: Set(Some(msg_id as i64)),
+                ..Default::default()
+            })
+            .filter(Column::Uuid.eq(self.uuid))
+            .filter(Column::PostedAt.is_null())
+            .exec(Database::global().connection())
+            .await
+            .is_ok_and(|r| r.rows_affected == 1)
+    }
+
+    // Status is compared in the query as well, so two moderators can't both win the same transition
+    async fn transition(&self, next: PhotoStatus, mut changes: ActiveModel) -> bool {
+        if !self.status.can_become(next) {
+            warn!("Photo {} can't change status from {:?} to {:?}", self.uuid, self.status, next);
+            return false;
+        }
+
+        changes.status = Set(next);
+
+        let res = Entity::update_many()
+            .set(changes)
+            .filter(Column::Uuid.eq(self.uuid))
+            .filter(Column::Status.eq(self.status))
+            .exec(Database::global().connection())
+            .await;
 
-        Entity::update(model).exec(Database::global().connection()).await.is_ok()
+        match res {
+            Ok(r) => r.rows_affected == 1,
+            Err(e) => {
+                error!("Can't update photo status: {e}");
+                false
+            }
+        }
+    }
+
+    /// Moderator's text replaces the author's caption, an empty one removes it from the post
+    pub async fn set_caption_override(&self, caption: Option<String>) -> bool {
+        Entity::update_many()
+            .col_expr(Column::CaptionOverride, Expr::value(caption))
+            .filter(Column::Uuid.eq(self.uuid))
+            .exec(Database::global().connection())
+            .await
+            .is_ok_and(|r| r.rows_affected == 1)
     }
 
-    pub async fn update_msg_id(&self, msg_id: i32) -> bool {
-        let mut model = self.clone().into_active_model();
-        model.msg_id = Set(Some(msg_id as i64));
+    pub fn final_caption(&self) -> Option<&str> {
+        self.caption_override.as_deref().or(self.caption.as_deref()).filter(|c| !c.is_empty())
+    }
 
-        Entity::update(model).exec(Database::global().connection()).await.is_ok()
+    /// Caption is typed by a user, so it has to be escaped before it goes to a message with HTML parse mode
+    pub fn caption_html(&self) -> Option<String> {
+        self.final_caption().map(|c| html_escape::encode_text(c).to_string())
+    }
+
+    /// Saves what has been learned from the file itself when it was sent
+    pub async fn set_inspection(&self, inspection: &PhotoInspection) -> bool {
+        Entity::update_many()
+            .set(ActiveModel {
+                mime_type: match &inspection.mime_type {
+                    Some(m) => Set(Some(m.clone())),
+                    None => NotSet,
+                },
+                phash: Set(inspection.phash),
+                latitude: Set(inspection.location.map(|l| l.0)),
+                longitude: Set(inspection.location.map(|l| l.1)),
+                private_metadata: Set((!inspection.private_metadata.is_empty()).then(|| inspection.private_metadata.join(", "))),
+                ..Default::default()
+            })
+            .filter(Column::Uuid.eq(self.uuid))
+            .exec(Database::global().connection())
+            .await
+            .is_ok_and(|r| r.rows_affected == 1)
+    }
+
+    pub async fn set_publish_location(&self, publish: bool) -> bool {
+        Entity::update_many()
+            .col_expr(Column::PublishLocation, Expr::value(publish))
+            .filter(Column::Uuid.eq(self.uuid))
+            .exec(Database::global().connection())
+            .await
+            .is_ok_and(|r| r.rows_affected == 1)
+    }
+
+    pub async fn set_watermark(&self, watermark: bool) -> bool {
+        Entity::update_many()
+            .col_expr(Column::Watermark, Expr::value(watermark))
+            .filter(Column::Uuid.eq(self.uuid))
+            .exec(Database::global().connection())
+            .await
+            .is_ok_and(|r| r.rows_affected == 1)
+    }
+
+    pub fn location(&self) -> Option<(f64, f64)> {
+        Some((self.latitude?, self.longitude?))
     }
 
     pub async fn user(&self) -> super::users::Model {
@@ -107,6 +410,10 @@ impl Model {
             .unwrap()
     }
 
+    pub async fn submission(&self) -> Option<super::submissions::Model> {
+        super::submissions::Entity::get_by_id(self.submission_uuid?).await
+    }
+
     pub async fn get_reactions(&self) -> Vec<super::reactions::Model> {
         Reactions::get_photos_reactions(self.uuid).await
     }
@@ -1,4 +1,8 @@
 pub use super::ban::Entity as Ban;
+pub use super::moderation_cards::Entity as ModerationCards;
+pub use super::moderation_events::Entity as ModerationEvents;
+pub use super::moderators::Entity as Moderators;
 pub use super::photos::Entity as Photos;
 pub use super::reactions::Entity as Reactions;
+pub use super::submissions::Entity as Submissions;
 pub use super::users::Entity as Users;
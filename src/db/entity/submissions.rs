@@ -0,0 +1,102 @@
+use crate::db::Database;
+use sea_orm::entity::prelude::*;
+use sea_orm::{QueryOrder, Set, sea_query::OnConflict};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "submissions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub uuid: Uuid,
+    pub user_id: i64,
+    pub media_group_id: String,
+    pub msg_id: Option<i64>,
+    pub created_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::users::Entity",
+        from = "Column::UserId",
+        to = "super::users::Column::UserId",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Users,
+    #[sea_orm(has_many = "super::photos::Entity")]
+    Photos,
+}
+
+impl Related<super::users::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Users.def()
+    }
+}
+
+impl Related<super::photos::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Photos.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Entity {
+    pub async fn get_or_create(user_id: i64, media_group_id: &str) -> Option<Model> {
+        let res = Self::insert(ActiveModel {
+            user_id: Set(user_id),
+            media_group_id: Set(media_group_id.to_string()),
+            ..Default::default()
+        })
+        .on_conflict(OnConflict::column(Column::MediaGroupId).do_nothing().to_owned())
+        .do_nothing()
+        .exec(Database::global().connection())
+        .await;
+
+        if let Err(e) = res {
+            error!("Can't add submission to database: {e}");
+        }
+
+        let res = Self::find()
+            .filter(Column::MediaGroupId.eq(media_group_id))
+            .one(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get submission from database: {e}");
+            None
+        })
+    }
+
+    pub async fn get_by_id(uuid: Uuid) -> Option<Model> {
+        let res = Self::find_by_id(uuid).one(Database::global().connection()).await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get submission from database: {e}");
+            None
+        })
+    }
+}
+
+impl Model {
+    pub async fn photos(&self) -> Vec<super::photos::Model> {
+        let res = super::photos::Entity::find()
+            .filter(super::photos::Column::SubmissionUuid.eq(self.uuid))
+            .order_by_asc(super::photos::Column::Position)
+            .all(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get submission photos from database: {e}");
+            Vec::new()
+        })
+    }
+
+    pub async fn user(&self) -> super::users::Model {
+        super::users::Entity::find_by_id(self.user_id)
+            .one(Database::global().connection())
+            .await
+            .expect("Can't get user")
+            .unwrap()
+    }
+}
@@ -12,6 +12,8 @@ pub struct Model {
     pub firstname: String,
     pub lastname: Option<String>,
     pub created_at: Option<DateTime>,
+    pub hourly_limit: Option<i32>,
+    pub pending_limit: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -20,6 +22,8 @@ pub enum Relation {
     Ban,
     #[sea_orm(has_many = "super::photos::Entity")]
     Photos,
+    #[sea_orm(has_many = "super::submissions::Entity")]
+    Submissions,
 }
 
 impl Related<super::ban::Entity> for Entity {
@@ -34,6 +38,12 @@ impl Related<super::photos::Entity> for Entity {
     }
 }
 
+impl Related<super::submissions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Submissions.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 impl Entity {
@@ -48,6 +58,54 @@ impl Entity {
             .await
             .is_ok()
     }
+
+    pub async fn get(user_id: i64) -> Option<Model> {
+        let res = Self::find_by_id(user_id).one(Database::global().connection()).await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get user from database: {e}");
+            None
+        })
+    }
+
+    pub async fn get_by_username(username: &str) -> Option<Model> {
+        let res = Self::find()
+            .filter(Column::Username.eq(username))
+            .one(Database::global().connection())
+            .await;
+
+        res.unwrap_or_else(|e| {
+            error!("Can't get user from database: {e}");
+            None
+        })
+    }
+
+    /// Overrides default submission limits for a trusted contributor, `None` falls back to defaults
+    pub async fn set_limits(user_id: i64, hourly_limit: Option<i32>, pending_limit: Option<i32>) -> bool {
+        let res = Self::update_many()
+            .col_expr(Column::HourlyLimit, Expr::value(hourly_limit))
+            .col_expr(Column::PendingLimit, Expr::value(pending_limit))
+            .filter(Column::UserId.eq(user_id))
+            .exec(Database::global().connection())
+            .await;
+
+        match res {
+            Ok(r) => r.rows_affected > 0,
+            Err(e) => {
+                error!("Can't update user limits: {e}");
+                false
+            }
+        }
+    }
+
+    /// Accepts either numeric user id or @username of someone who has already written to the bot
+    pub async fn resolve(target: &str) -> Option<i64> {
+        if let Ok(user_id) = target.parse::<i64>() {
+            return Some(user_id);
+        }
+
+        Self::get_by_username(target.strip_prefix('@')?).await.map(|u| u.user_id)
+    }
 }
 
 impl Model {
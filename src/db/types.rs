@@ -1,4 +1,4 @@
-use crate::db::entity::reactions::ReactionType;
+use crate::db::entity::{photos, reactions::ReactionType};
 use sea_orm::Set;
 use teloxide::types::{Message, ReactionCount, ReactionType as TgReactionType, User};
 
@@ -22,11 +22,23 @@ impl From<Message> for super::entity::photos::ActiveModel {
             user_id: Set(value.from.as_ref().unwrap().id.0 as i64),
             file_id: Set(doc.file.id.clone().0),
             mime_type: Set(doc.mime_type.clone().map(|u| u.to_string())),
+            caption: Set(value.caption().map(str::trim).filter(|c| !c.is_empty()).map(shorten_caption)),
             ..Default::default()
         }
     }
 }
 
+/// Long captions are cut, so the post still fits into the limit of Telegram
+fn shorten_caption(caption: &str) -> String {
+    if caption.chars().count() <= photos::MAX_CAPTION_LENGTH {
+        return caption.to_string();
+    }
+
+    let short: String = caption.chars().take(photos::MAX_CAPTION_LENGTH - 1).collect();
+
+    format!("{}…", short.trim_end())
+}
+
 pub struct Reactions {
     pub r#type: ReactionType,
     pub content: Option<String>,
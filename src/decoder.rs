@@ -0,0 +1,395 @@
+use crate::image::Image;
+use crate::metadata::{self, Tiff};
+use image::{ExtendedColorType, ImageEncoder, ImageError, ImageReader, codecs::jpeg::JpegEncoder};
+use libheif_rs::{ColorSpace, HeifContext, HeifError, ImageHandle, ItemId, LibHeif, RgbChroma};
+use std::{fmt, fs::File, io, io::BufWriter, path::Path, process::Command};
+
+const JPEG_QUALITY: u8 = 90;
+const HEIF_CONVERT: &str = "heif-convert";
+
+const DNG_VERSION: u16 = 0xC612;
+const SUB_IFDS: u16 = 0x014A;
+const COMPRESSION: u16 = 0x0103;
+const PHOTOMETRIC: u16 = 0x0106;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+// Old-style and baseline JPEG compressions, the latter is also used for lossless raw data
+const JPEG_COMPRESSIONS: [u32; 2] = [6, 7];
+// RGB and YCbCr, raw sensor data is stored as CFA or LinearRaw
+const PREVIEW_PHOTOMETRICS: [u32; 2] = [2, 6];
+const MAX_IFDS: usize = 32;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Heif(HeifError),
+    Encode(ImageError),
+    Io(io::Error),
+    ToolNotFound(&'static str),
+    ToolFailed(String),
+    NoOutput(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Heif(e) => write!(f, "libheif error: {e}"),
+            DecodeError::Encode(e) => write!(f, "JPEG encoding failed: {e}"),
+            DecodeError::Io(e) => write!(f, "IO error: {e}"),
+            DecodeError::ToolNotFound(tool) => write!(f, "{tool} is not installed"),
+            DecodeError::ToolFailed(e) => write!(f, "Decoding tool failed: {e}"),
+            DecodeError::NoOutput(e) => write!(f, "Decoder has not produced an image: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<HeifError> for DecodeError {
+    fn from(value: HeifError) -> Self {
+        DecodeError::Heif(value)
+    }
+}
+
+impl From<ImageError> for DecodeError {
+    fn from(value: ImageError) -> Self {
+        DecodeError::Encode(value)
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(value: io::Error) -> Self {
+        DecodeError::Io(value)
+    }
+}
+
+/// Turns a HEIF file into a JPEG which is displayed the same way as the original:
+/// rotation is applied to pixels, EXIF and the ICC profile are kept
+pub trait HeifDecoder {
+    fn name(&self) -> &'static str;
+
+    fn decode(&self, src: &Path, dst: &Path) -> Result<(), DecodeError>;
+}
+
+/// Decodes in process with libheif
+pub struct LibheifDecoder;
+
+/// Runs `heif-convert`, it does the same work but needs the binary installed
+pub struct HeifConvertDecoder {
+    program: &'static str,
+}
+
+impl Default for HeifConvertDecoder {
+    fn default() -> Self {
+        Self { program: HEIF_CONVERT }
+    }
+}
+
+pub fn decode_heif(src: &Path, dst: &Path) -> Result<(), DecodeError> {
+    decode_with(&[&LibheifDecoder, &HeifConvertDecoder::default()], src, dst)
+}
+
+/// Tries decoders one by one, when all of them fail the error of the first one is returned
+fn decode_with(decoders: &[&dyn HeifDecoder], src: &Path, dst: &Path) -> Result<(), DecodeError> {
+    let mut first_error = None;
+
+    for decoder in decoders.iter() {
+        match decoder.decode(src, dst) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                warn!("{} can't decode {}: {e}", decoder.name(), src.to_string_lossy());
+
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_error.unwrap_or_else(|| DecodeError::NoOutput(src.to_string_lossy().to_string())))
+}
+
+impl HeifDecoder for LibheifDecoder {
+    fn name(&self) -> &'static str {
+        "libheif"
+    }
+
+    fn decode(&self, src: &Path, dst: &Path) -> Result<(), DecodeError> {
+        let data = std::fs::read(src)?;
+        let context = HeifContext::read_from_bytes(&data)?;
+        let handle = context.primary_image_handle()?;
+        // Rotation and mirroring from the container are applied here
+        let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+        let plane = match image.planes().interleaved {
+            Some(p) => p,
+            None => return Err(DecodeError::NoOutput("decoded image has no RGB plane".to_string())),
+        };
+
+        let row_size = plane.width as usize * 3;
+        let mut pixels = Vec::with_capacity(row_size * plane.height as usize);
+
+        for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+            pixels.extend_from_slice(&row[..row_size]);
+        }
+
+        let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(File::create(dst)?), JPEG_QUALITY);
+
+        if let Some(profile) = handle.color_profile_raw() {
+            encoder.set_icc_profile(profile.data).map_err(ImageError::Unsupported)?;
+        }
+
+        if let Some(exif) = get_exif(&handle) {
+            encoder.set_exif_metadata(exif).map_err(ImageError::Unsupported)?;
+        }
+
+        encoder.write_image(&pixels, plane.width, plane.height, ExtendedColorType::Rgb8)?;
+
+        Ok(())
+    }
+}
+
+impl HeifDecoder for HeifConvertDecoder {
+    fn name(&self) -> &'static str {
+        self.program
+    }
+
+    fn decode(&self, src: &Path, dst: &Path) -> Result<(), DecodeError> {
+        let output = match Command::new(self.program)
+            .args(["-q", &JPEG_QUALITY.to_string()])
+            .arg(src)
+            .arg(dst)
+            .output()
+        {
+            Ok(o) => o,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(DecodeError::ToolNotFound(self.program)),
+            Err(e) => return Err(e.into()),
+        };
+
+        debug!("{output:?}");
+
+        if !output.status.success() {
+            return Err(DecodeError::ToolFailed(format!(
+                "{}, {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        // Files with several images are written with suffixes, the expected file is missing then
+        if !dst.exists() {
+            return Err(DecodeError::NoOutput(dst.to_string_lossy().to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// HEIF keeps EXIF with a 4-byte offset of the TIFF header in front, JPEG needs the TIFF block only
+fn get_exif(handle: &ImageHandle) -> Option<Vec<u8>> {
+    let mut ids: [ItemId; 1] = [0];
+
+    if handle.metadata_block_ids(&mut ids, b"Exif") == 0 {
+        return None;
+    }
+
+    let block = handle.metadata(ids[0]).ok()?;
+    let offset = u32::from_be_bytes(block.get(..4)?.try_into().ok()?) as usize;
+    let mut tiff = block.get(4 + offset..)?.to_vec();
+
+    if let Err(e) = metadata::reset_orientation(&mut tiff) {
+        warn!("Can't reset orientation: {e}");
+    }
+
+    Some(tiff)
+}
+
+/// Reads the size from headers, pixels are not decoded
+pub fn read_dimensions(src: &Path) -> Result<(u32, u32), DecodeError> {
+    Ok(ImageReader::open(src)?.with_guessed_format()?.into_dimensions()?)
+}
+
+/// Same as [`read_dimensions`] for HEIF containers, the size is taken from the primary image handle
+pub fn read_heif_dimensions(src: &Path) -> Result<(u32, u32), DecodeError> {
+    let data = std::fs::read(src)?;
+    let context = HeifContext::read_from_bytes(&data)?;
+    let handle = context.primary_image_handle()?;
+
+    Ok((handle.width(), handle.height()))
+}
+
+/// Converts formats which the image crate can read, like WebP and TIFF, to JPEG keeping orientation and colors
+pub fn decode_image(src: &Path, dst: &Path) -> Result<(), DecodeError> {
+    Image::open(src)?.write(dst)?;
+
+    Ok(())
+}
+
+/// DNG is a TIFF with the DNGVersion tag in the first IFD
+pub fn is_dng(data: &[u8]) -> bool {
+    Tiff::new(data)
+        .and_then(|tiff| tiff.find_entry(tiff.read_u32(4)? as usize, DNG_VERSION))
+        .is_ok_and(|entry| entry.is_some())
+}
+
+/// Raw sensor data can't be developed here, so the largest JPEG preview embedded by the camera is published
+pub fn decode_dng(src: &Path, dst: &Path) -> Result<(), DecodeError> {
+    let data = std::fs::read(src)?;
+    let preview = match find_dng_preview(&data) {
+        Ok(Some((offset, length))) => &data[offset..offset + length],
+        Ok(None) => return Err(DecodeError::NoOutput("DNG has no JPEG preview".to_string())),
+        Err(e) => return Err(DecodeError::NoOutput(format!("Can't read DNG: {e}"))),
+    };
+
+    std::fs::write(dst, preview)?;
+
+    Ok(())
+}
+
+/// Returns the offset and the length of the preview
+fn find_dng_preview(data: &[u8]) -> anyhow::Result<Option<(usize, usize)>> {
+    let tiff = Tiff::new(data)?;
+    let mut ifds = vec![tiff.read_u32(4)? as usize];
+    let mut preview: Option<(usize, usize)> = None;
+    let mut i = 0;
+
+    while i < ifds.len() && i < MAX_IFDS {
+        let ifd = ifds[i];
+        i += 1;
+
+        if let Some(entry) = tiff.find_entry(ifd, SUB_IFDS)? {
+            ifds.extend(tiff.read_values(entry)?.into_iter().map(|o| o as usize));
+        }
+
+        if let Some(next) = tiff.next_ifd(ifd)? {
+            ifds.push(next);
+        }
+
+        let value = |tag| -> anyhow::Result<Option<u32>> {
+            Ok(match tiff.find_entry(ifd, tag)? {
+                Some(entry) => tiff.read_values(entry)?.first().copied(),
+                None => None,
+            })
+        };
+
+        if !value(COMPRESSION)?.is_some_and(|c| JPEG_COMPRESSIONS.contains(&c))
+            || !value(PHOTOMETRIC)?.is_some_and(|p| PREVIEW_PHOTOMETRICS.contains(&p))
+        {
+            continue;
+        }
+
+        let (offset, length) = match (value(JPEG_OFFSET)?, value(JPEG_LENGTH)?) {
+            (Some(offset), Some(length)) => (offset as usize, length as usize),
+            _ => match (value(STRIP_OFFSETS)?, value(STRIP_BYTE_COUNTS)?) {
+                (Some(offset), Some(length)) => (offset as usize, length as usize),
+                _ => continue,
+            },
+        };
+
+        if tiff.bytes(offset, length).is_ok_and(|jpeg| jpeg.starts_with(&[0xFF, 0xD8])) && preview.is_none_or(|(_, l)| l < length) {
+            preview = Some((offset, length));
+        }
+    }
+
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{HEIC_FIXTURE as FIXTURE, TempFile, tiff_with_orientation};
+    use exif::{In, Reader, Tag};
+    use std::io::BufReader;
+
+    struct FakeDecoder(Result<(), &'static str>);
+
+    impl HeifDecoder for FakeDecoder {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn decode(&self, _src: &Path, _dst: &Path) -> Result<(), DecodeError> {
+            self.0.map_err(|e| DecodeError::ToolFailed(e.to_string()))
+        }
+    }
+
+    #[test]
+    fn libheif_decodes_fixture() {
+        let dst = TempFile::new("jpg");
+
+        LibheifDecoder.decode(Path::new(FIXTURE), &dst.0).unwrap();
+
+        let (width, height) = read_heif_dimensions(Path::new(FIXTURE)).unwrap();
+        let decoded = image::open(&dst.0).unwrap();
+
+        assert_eq!((decoded.width(), decoded.height()), (width, height));
+    }
+
+    #[test]
+    fn libheif_resets_exif_orientation() {
+        let dst = TempFile::new("jpg");
+
+        LibheifDecoder.decode(Path::new(FIXTURE), &dst.0).unwrap();
+
+        let exif = Reader::new()
+            .read_from_container(&mut BufReader::new(File::open(&dst.0).unwrap()))
+            .unwrap();
+        let orientation = exif.get_field(Tag::Orientation, In::PRIMARY).unwrap();
+
+        assert_eq!(orientation.value.get_uint(0), Some(1));
+    }
+
+    #[test]
+    fn corrupt_file_is_an_error() {
+        let src = TempFile::new("heic");
+        let dst = TempFile::new("jpg");
+        let mut data = std::fs::read(FIXTURE).unwrap();
+
+        data.truncate(512);
+        std::fs::write(&src.0, data).unwrap();
+
+        assert!(decode_heif(&src.0, &dst.0).is_err());
+    }
+
+    #[test]
+    fn missing_heif_convert_is_reported() {
+        let decoder = HeifConvertDecoder {
+            program: "heif-convert-which-is-not-installed",
+        };
+        let dst = TempFile::new("jpg");
+
+        assert!(matches!(
+            decoder.decode(Path::new(FIXTURE), &dst.0),
+            Err(DecodeError::ToolNotFound("heif-convert-which-is-not-installed"))
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_next_decoder() {
+        let dst = TempFile::new("jpg");
+        let decoders: [&dyn HeifDecoder; 2] = [&FakeDecoder(Err("first")), &FakeDecoder(Ok(()))];
+
+        assert!(decode_with(&decoders, Path::new(FIXTURE), &dst.0).is_ok());
+    }
+
+    #[test]
+    fn returns_first_error_when_all_fail() {
+        let dst = TempFile::new("jpg");
+        let missing = HeifConvertDecoder {
+            program: "heif-convert-which-is-not-installed",
+        };
+        let decoders: [&dyn HeifDecoder; 2] = [&FakeDecoder(Err("first")), &missing];
+
+        assert!(matches!(
+            decode_with(&decoders, Path::new(FIXTURE), &dst.0),
+            Err(DecodeError::ToolFailed(e)) if e == "first"
+        ));
+    }
+
+    #[test]
+    fn orientation_is_reset_in_tiff_block() {
+        let mut tiff = tiff_with_orientation(6);
+
+        metadata::reset_orientation(&mut tiff).unwrap();
+
+        assert_eq!(&tiff[18..20], &[1, 0]);
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::{Error, bail};
+use chrono::NaiveDateTime;
 use exif::{Exif, In, Reader, Tag, Value};
 use inflector::Inflector;
 use std::{io::BufReader, path::Path};
@@ -34,13 +35,46 @@ impl ExifLoader {
         None
     }
 
-    // pub fn get_lens_model(&self) -> Option<String> {
-    //     if let Some(field) = self.get_field_string(&Tag::LensModel) {
-    //         return Some(field);
-    //     }
+    pub fn get_lens_model(&self) -> Option<String> {
+        if let Some(field) = self.get_field_string(&Tag::LensModel) {
+            return Some(field);
+        }
+
+        None
+    }
+
+    pub fn get_date_time(&self) -> Option<String> {
+        let field = self.get_field_string(&Tag::DateTimeOriginal)?;
+
+        match NaiveDateTime::parse_from_str(field.trim(), "%Y:%m:%d %H:%M:%S") {
+            Ok(date) => Some(date.format("%d.%m.%Y %H:%M").to_string()),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_exposure_bias(&self) -> Option<String> {
+        if let Some(field) = self.get_field_string(&Tag::ExposureBiasValue) {
+            return Some(format!("{field}EV"));
+        }
+
+        None
+    }
 
-    //     None
-    // }
+    pub fn get_focal_length_35mm(&self) -> Option<String> {
+        if let Some(field) = self.get_field_string(&Tag::FocalLengthIn35mmFilm) {
+            return Some(format!("({field}mm eq.)"));
+        }
+
+        None
+    }
+
+    /// Lowest bit of the Flash tag tells whether the flash has fired
+    pub fn is_flash_fired(&self) -> bool {
+        match self.exif.get_field(Tag::Flash, In::PRIMARY).and_then(|f| f.value.get_uint(0)) {
+            Some(v) => v & 1 == 1,
+            None => false,
+        }
+    }
 
     pub fn get_software(&self) -> Option<String> {
         if let Some(field) = self.get_field_string(&Tag::Software) {
@@ -82,11 +116,48 @@ impl ExifLoader {
         None
     }
 
+    /// Returns latitude and longitude in decimal degrees
+    pub fn get_gps(&self) -> Option<(f64, f64)> {
+        let latitude = self.get_coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+        let longitude = self.get_coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+
+        // Some cameras write zeroes when there is no fix
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) || (latitude == 0.0 && longitude == 0.0) {
+            return None;
+        }
+
+        Some((latitude, longitude))
+    }
+
+    /// Converts degrees, minutes and seconds rationals, negative for southern and western hemispheres
+    fn get_coordinate(&self, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+        let field = self.exif.get_field(tag, In::PRIMARY)?;
+        let dms = match field.value {
+            Value::Rational(ref v) if v.len() == 3 && v.iter().all(|r| r.denom != 0) => v,
+            _ => return None,
+        };
+
+        let value = dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0;
+        let negative = match self.exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+            Some(Value::Ascii(v)) => v.first().and_then(|r| r.first()) == Some(&negative_ref),
+            _ => false,
+        };
+
+        Some(if negative { -value } else { value })
+    }
+
     pub fn get_photo_info_string(&self) -> Option<String> {
-        let infos = vec![self.get_focal_number(), self.get_exposure_time(), self.get_focal_length(), self.get_iso()]
-            .into_iter()
-            .flatten()
-            .collect::<Vec<String>>();
+        let infos = vec![
+            self.get_focal_number(),
+            self.get_exposure_time(),
+            self.get_focal_length(),
+            self.get_focal_length_35mm(),
+            self.get_iso(),
+            self.get_exposure_bias(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<String>>();
 
         if !infos.is_empty() {
             return Some(infos.join(" "));
@@ -126,6 +197,7 @@ impl ExifLoader {
                     let num = v[0].num as f64;
 
                     let res = match field.tag {
+                        Tag::ExposureTime if num >= denom => format!("{:.1}", num / denom),
                         Tag::ExposureTime => format!("1/{:.0}", denom / num),
                         Tag::FNumber => format!("{:.2}", num / denom),
                         Tag::FocalLength => format!("{:.2}", num / denom),
@@ -141,7 +213,21 @@ impl ExifLoader {
                         None
                     }
                 }
-                Value::Short(ref v) if !v.is_empty() => Some(field.display_value().to_string()),
+                Value::SRational(ref v) if !v.is_empty() && v[0].denom != 0 => {
+                    let value = v[0].to_f64();
+
+                    match field.tag {
+                        // Zero compensation is not worth mentioning
+                        Tag::ExposureBiasValue if value.abs() < 0.05 => None,
+                        Tag::ExposureBiasValue => Some(format!("{value:+.1}")),
+                        _ => Some(field.display_value().to_string()),
+                    }
+                }
+                Value::Short(ref v) if !v.is_empty() => match field.tag {
+                    Tag::FocalLengthIn35mmFilm if v[0] == 0 => None,
+                    _ => Some(field.display_value().to_string()),
+                },
+                Value::Long(ref v) if !v.is_empty() => Some(v[0].to_string()),
                 _ => None,
             };
         }
@@ -0,0 +1,55 @@
+use envconfig::Envconfig;
+use geo::{Contains, GeometryCollection, MultiPolygon, Point, Polygon};
+use geojson::GeoJson;
+use once_cell::sync::OnceCell;
+
+pub static INSTANCE: OnceCell<Geofence> = OnceCell::new();
+
+const DEFAULT_AREA: &str = include_str!("../assets/innopolis.geojson");
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct GeofenceConfig {
+    #[envconfig(from = "GEOFENCE_PATH")]
+    pub path: Option<String>,
+}
+
+/// Town borders used to tell moderators whether a photo was taken in town
+pub struct Geofence {
+    area: MultiPolygon<f64>,
+}
+
+impl Geofence {
+    pub fn new(config: &GeofenceConfig) -> Self {
+        let source = match &config.path {
+            Some(path) => std::fs::read_to_string(path).expect("Can't read geofence file"),
+            None => DEFAULT_AREA.to_string(),
+        };
+        let geojson: GeoJson = source.parse().expect("Can't parse geofence file");
+        let collection = GeometryCollection::<f64>::try_from(&geojson).expect("Can't convert geofence geometry");
+
+        let polygons: Vec<Polygon<f64>> = collection
+            .into_iter()
+            .flat_map(|g| match g {
+                geo::Geometry::Polygon(p) => vec![p],
+                geo::Geometry::MultiPolygon(mp) => mp.0,
+                _ => Vec::new(),
+            })
+            .collect();
+
+        if polygons.is_empty() {
+            warn!("Geofence has no polygons, every photo will be treated as taken out of town");
+        }
+
+        Self {
+            area: MultiPolygon::new(polygons),
+        }
+    }
+
+    pub fn global() -> &'static Geofence {
+        INSTANCE.get().expect("Geofence is not initialized")
+    }
+
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        self.area.contains(&Point::new(longitude, latitude))
+    }
+}
@@ -1,28 +1,114 @@
-use image as img;
-use image::DynamicImage;
-use std::path::Path;
+use image::{
+    DynamicImage, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageReader, ImageResult, RgbaImage,
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    imageops::{self, FilterType},
+    metadata::Orientation,
+};
+use std::{fmt, fs::File, io::BufWriter, path::Path, str::FromStr};
+
+/// Telegram limits for photos sent with `send_photo`
+const MAX_PHOTO_SIZE: usize = 10 * 1024 * 1024;
+const MAX_DIMENSIONS_SUM: u32 = 10000;
+const MAX_ASPECT_RATIO: u32 = 20;
+
+const MAX_QUALITY: u8 = 95;
+const MIN_QUALITY: u8 = 60;
+const MAX_ENCODING_ATTEMPTS: usize = 5;
+
+#[derive(Debug)]
+pub enum PhotoLimitError {
+    AspectRatio { width: u32, height: u32 },
+    TooLarge { size: usize },
+    Encoding(ImageError),
+}
+
+impl fmt::Display for PhotoLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhotoLimitError::AspectRatio { width, height } => {
+                write!(f, "Aspect ratio of {width}x{height} is over {MAX_ASPECT_RATIO}:1")
+            }
+            PhotoLimitError::TooLarge { size } => write!(f, "Photo is still {size} bytes with the lowest quality"),
+            PhotoLimitError::Encoding(e) => write!(f, "Encoding failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PhotoLimitError {}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(format!("Unknown corner {s}")),
+        }
+    }
+}
 
 pub struct Image {
     im: DynamicImage,
+    icc_profile: Option<Vec<u8>>,
+    reoriented: bool,
 }
 
 impl Image {
-    pub fn new(path: &Path) -> Self {
-        Image {
-            im: img::open(path).unwrap(),
-        }
+    pub fn try_new(path: &Path) -> Option<Self> {
+        Self::open(path)
+            .inspect_err(|e| warn!("Can't open image {}: {e}", path.to_string_lossy()))
+            .ok()
     }
 
-    pub fn scale(&mut self, ex: f32) -> &mut Image {
-        let max_px = if self.im.width() > self.im.height() {
-            (self.im.width() as f32 * ex) as u32
-        } else {
-            (self.im.height() as f32 * ex) as u32
-        };
+    /// Opens the image upright: EXIF orientation is applied to pixels, so outputs are saved without it.
+    /// The ICC profile is kept to be written back on saving
+    pub fn open(path: &Path) -> ImageResult<Self> {
+        let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+        let icc_profile = decoder.icc_profile().unwrap_or_default();
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let mut im = DynamicImage::from_decoder(decoder)?;
 
-        self.im = self.im.thumbnail(max_px, max_px);
+        im.apply_orientation(orientation);
 
-        self
+        Ok(Image {
+            im,
+            icc_profile,
+            reoriented: orientation != Orientation::NoTransforms,
+        })
+    }
+
+    /// Whether pixels have been rotated or flipped according to EXIF when the image was opened
+    pub fn is_reoriented(&self) -> bool {
+        self.reoriented
+    }
+
+    /// Difference hash: every bit tells whether a pixel is brighter than its right neighbour on a 9x8 grayscale copy
+    pub fn dhash(&self) -> u64 {
+        let small = self.im.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+        let mut hash = 0u64;
+
+        for y in 0..8 {
+            for x in 0..8 {
+                hash <<= 1;
+
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    hash |= 1;
+                }
+            }
+        }
+
+        hash
     }
 
     pub fn resize(&mut self, px: u32) -> &mut Image {
@@ -35,15 +121,230 @@ impl Image {
         (self.im.width(), self.im.height())
     }
 
-    pub fn get_scaling(&self, max_size: u32) -> f32 {
+    /// Blends the mark into the corner, it's kept off the edges by half of its height
+    pub fn overlay(&mut self, mark: &RgbaImage, corner: Corner) {
+        let (width, height) = self.get_size();
+        let margin = mark.height() / 2;
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => width.saturating_sub(mark.width() + margin),
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => height.saturating_sub(mark.height() + margin),
+        };
+
+        let mut canvas = self.im.to_rgba8();
+
+        imageops::overlay(&mut canvas, mark, x as i64, y as i64);
+        self.im = DynamicImage::ImageRgba8(canvas);
+    }
+
+    /// Whether the photo can be sent with `send_photo` as is
+    pub fn fits_photo_limits(&self, file_size: u64) -> bool {
+        let (width, height) = self.get_size();
+
+        file_size <= MAX_PHOTO_SIZE as u64 && width + height <= MAX_DIMENSIONS_SUM && width.max(height) <= width.min(height) * MAX_ASPECT_RATIO
+    }
+
+    /// Encodes JPEG for `send_photo` keeping as much detail as possible. The size is reduced only to meet
+    /// the dimensions limit, then the best quality which fits the file size limit is searched.
+    /// When even the lowest quality is too big, the photo is scaled down by the overflow and the search is repeated
+    pub fn encode_for_photo_limits(&self) -> Result<Vec<u8>, PhotoLimitError> {
         let (width, height) = self.get_size();
-        let scale_y = max_size as f32 / width as f32;
-        let scale_x = max_size as f32 / height as f32;
 
-        if scale_y > scale_x { scale_x } else { scale_y }
+        if width.max(height) > width.min(height) * MAX_ASPECT_RATIO {
+            return Err(PhotoLimitError::AspectRatio { width, height });
+        }
+
+        let mut im = DynamicImage::ImageRgb8(self.im.to_rgb8());
+
+        if width + height > MAX_DIMENSIONS_SUM {
+            im = self.scaled(&im, MAX_DIMENSIONS_SUM as f32 / (width + height) as f32);
+        }
+
+        let mut smallest = 0;
+
+        for _ in 0..MAX_ENCODING_ATTEMPTS {
+            // Most photos fit with the best quality, so it's checked before the search
+            let data = self.encode_jpeg(&im, MAX_QUALITY)?;
+
+            if data.len() <= MAX_PHOTO_SIZE {
+                return Ok(data);
+            }
+
+            let (mut low, mut high) = (MIN_QUALITY, MAX_QUALITY - 1);
+            let mut best = None;
+
+            while low <= high {
+                let quality = (low + high) / 2;
+                let data = self.encode_jpeg(&im, quality)?;
+
+                if data.len() <= MAX_PHOTO_SIZE {
+                    best = Some(data);
+                    low = quality + 1;
+                } else {
+                    smallest = data.len();
+                    high = quality - 1;
+                }
+            }
+
+            if let Some(data) = best {
+                return Ok(data);
+            }
+
+            // File size is roughly proportional to the number of pixels
+            im = self.scaled(&im, (MAX_PHOTO_SIZE as f32 / smallest as f32).sqrt() * 0.95);
+        }
+
+        Err(PhotoLimitError::TooLarge { size: smallest })
+    }
+
+    fn scaled(&self, im: &DynamicImage, scale: f32) -> DynamicImage {
+        let width = ((im.width() as f32 * scale) as u32).max(1);
+        let height = ((im.height() as f32 * scale) as u32).max(1);
+
+        im.resize(width, height, FilterType::Lanczos3)
+    }
+
+    fn encode_jpeg(&self, im: &DynamicImage, quality: u8) -> Result<Vec<u8>, PhotoLimitError> {
+        let mut data = Vec::new();
+        let mut encoder = JpegEncoder::new_with_quality(&mut data, quality);
+
+        self.set_icc_profile(&mut encoder);
+        im.write_with_encoder(encoder).map_err(PhotoLimitError::Encoding)?;
+
+        Ok(data)
     }
 
     pub fn save(&self, path: &Path) -> bool {
-        self.im.save(path).is_ok()
+        self.write(path)
+            .inspect_err(|e| warn!("Can't save image {}: {e}", path.to_string_lossy()))
+            .is_ok()
+    }
+
+    pub fn write(&self, path: &Path) -> ImageResult<()> {
+        let format = ImageFormat::from_path(path)?;
+        let writer = BufWriter::new(File::create(path)?);
+
+        match format {
+            // JPEG has no alpha channel
+            ImageFormat::Jpeg => {
+                let mut encoder = JpegEncoder::new(writer);
+
+                self.set_icc_profile(&mut encoder);
+                DynamicImage::ImageRgb8(self.im.to_rgb8()).write_with_encoder(encoder)
+            }
+            ImageFormat::Png => {
+                let mut encoder = PngEncoder::new(writer);
+
+                self.set_icc_profile(&mut encoder);
+                self.im.write_with_encoder(encoder)
+            }
+            _ => self.im.save_with_format(path, format),
+        }
+    }
+
+    fn set_icc_profile(&self, encoder: &mut impl ImageEncoder) {
+        if let Some(profile) = &self.icc_profile
+            && let Err(e) = encoder.set_icc_profile(profile.clone())
+        {
+            warn!("Can't keep ICC profile: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TempFile, tiff_with_orientation};
+    use image::{Rgb, RgbImage, imageops};
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    /// Wide image with a different color in every quadrant, so any rotation or flip changes it
+    fn upright() -> RgbImage {
+        RgbImage::from_fn(64, 32, |x, y| {
+            Rgb(match (x < 32, y < 16) {
+                (true, true) => RED,
+                (false, true) => GREEN,
+                (true, false) => BLUE,
+                (false, false) => WHITE,
+            })
+        })
+    }
+
+    /// Stores pixels the way a camera does for the orientation, so they are upright once it's applied
+    fn write_oriented(orientation: u8, path: &Path) {
+        let img = upright();
+        let stored = match orientation {
+            1 => img,
+            2 => imageops::flip_horizontal(&img),
+            3 => imageops::rotate180(&img),
+            4 => imageops::flip_vertical(&img),
+            5 => imageops::flip_horizontal(&imageops::rotate90(&img)),
+            6 => imageops::rotate270(&img),
+            7 => imageops::flip_horizontal(&imageops::rotate270(&img)),
+            8 => imageops::rotate90(&img),
+            _ => unreachable!(),
+        };
+        let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(File::create(path).unwrap()), 100);
+
+        encoder.set_exif_metadata(tiff_with_orientation(orientation)).unwrap();
+        DynamicImage::ImageRgb8(stored).write_with_encoder(encoder).unwrap();
+    }
+
+    fn assert_color(img: &Image, x: u32, y: u32, expected: [u8; 3]) {
+        let pixel = img.im.to_rgb8().get_pixel(x, y).0;
+
+        assert!(
+            pixel.iter().zip(expected).all(|(a, b)| a.abs_diff(b) < 32),
+            "pixel at {x}x{y} is {pixel:?}, expected {expected:?}"
+        );
+    }
+
+    #[test]
+    fn applies_every_orientation() {
+        for orientation in 1..=8 {
+            let path = TempFile::new("jpg");
+
+            write_oriented(orientation, &path.0);
+
+            let img = Image::open(&path.0).unwrap();
+
+            assert_eq!(img.get_size(), (64, 32), "orientation {orientation}");
+            assert_eq!(img.is_reoriented(), orientation != 1, "orientation {orientation}");
+            assert_color(&img, 8, 8, RED);
+            assert_color(&img, 56, 8, GREEN);
+            assert_color(&img, 8, 24, BLUE);
+            assert_color(&img, 56, 24, WHITE);
+        }
+    }
+
+    #[test]
+    fn keeps_icc_profile() {
+        let profile: Vec<u8> = (0..=255).collect();
+        let img = Image {
+            im: DynamicImage::ImageRgb8(upright()),
+            icc_profile: Some(profile.clone()),
+            reoriented: false,
+        };
+
+        for extension in ["jpg", "png"] {
+            let path = TempFile::new(extension);
+
+            img.write(&path.0).unwrap();
+
+            assert_eq!(Image::open(&path.0).unwrap().icc_profile, Some(profile.clone()), "{extension}");
+        }
+
+        let path = TempFile::new("jpg");
+
+        std::fs::write(&path.0, img.encode_for_photo_limits().unwrap()).unwrap();
+
+        assert_eq!(Image::open(&path.0).unwrap().icc_profile, Some(profile));
     }
 }
@@ -7,7 +7,13 @@ extern crate core;
 extern crate inflector;
 
 use crate::bot::{BotConfig, BotManager};
+use crate::geofence::{Geofence, GeofenceConfig};
+use crate::pool::{ImagePool, ImagePoolConfig};
 use crate::redis::{RedisConfig, RedisManager};
+use crate::scheduler::{Scheduler, SchedulerConfig};
+use crate::template::{TemplateConfig, Templates};
+use crate::watermark::{Watermark, WatermarkConfig};
+use crate::workspace::{Workspace, WorkspaceConfig};
 use dotenv::dotenv;
 use envconfig::Envconfig;
 use std::sync::Arc;
@@ -18,10 +24,20 @@ use teloxide::{
 
 mod bot;
 mod db;
+mod decoder;
 mod exif;
+mod geofence;
 mod image;
+mod metadata;
+mod pool;
 mod redis;
+mod scheduler;
+mod template;
+#[cfg(test)]
+mod test_utils;
 mod types;
+mod watermark;
+mod workspace;
 
 #[derive(Clone)]
 pub struct Application {
@@ -38,6 +54,18 @@ pub struct Config {
     pub bot_config: BotConfig,
     #[envconfig(nested)]
     pub redis_config: RedisConfig,
+    #[envconfig(nested)]
+    pub scheduler_config: SchedulerConfig,
+    #[envconfig(nested)]
+    pub geofence_config: GeofenceConfig,
+    #[envconfig(nested)]
+    pub template_config: TemplateConfig,
+    #[envconfig(nested)]
+    pub workspace_config: WorkspaceConfig,
+    #[envconfig(nested)]
+    pub image_pool_config: ImagePoolConfig,
+    #[envconfig(nested)]
+    pub watermark_config: WatermarkConfig,
 }
 
 impl Application {
@@ -75,14 +103,42 @@ async fn main() {
     db.migrate().await.expect("Can't migrate");
 
     db::INSTANCE.set(db).expect("Can't set database");
+
+    if !db::entity::prelude::Moderators::ensure_owner(app.config.bot_config.admin_id).await {
+        error!("Can't register owner {}", app.config.bot_config.admin_id);
+    }
     bot::INSTANCE.set(bot).expect("Can't set bot");
     redis::INSTANCE.set(redis).expect("Can't set redis");
+    geofence::INSTANCE
+        .set(Geofence::new(&app.config.geofence_config))
+        .unwrap_or_else(|_| panic!("Can't set geofence"));
+    template::INSTANCE
+        .set(Templates::new(&app.config.template_config))
+        .unwrap_or_else(|_| panic!("Can't set templates"));
+    workspace::INSTANCE
+        .set(Workspace::new(&app.config.workspace_config))
+        .unwrap_or_else(|_| panic!("Can't set workspace"));
+    pool::INSTANCE
+        .set(ImagePool::new(&app.config.image_pool_config))
+        .unwrap_or_else(|_| panic!("Can't set image pool"));
+    watermark::INSTANCE
+        .set(Watermark::new(&app.config.watermark_config))
+        .unwrap_or_else(|_| panic!("Can't set watermark"));
+
+    let swept = Workspace::global().sweep();
+
+    if swept > 0 {
+        warn!("Removed {swept} orphaned job directories");
+    }
 
     info!("Bot version: {}", &app.config.version);
 
     info!("Starting subscriber...");
     RedisManager::global().subscriber(&app.config.bot_config).await;
 
+    info!("Starting scheduler...");
+    Scheduler::new(&app.config.scheduler_config).start();
+
     info!("Starting dispatch...");
     BotManager::global()
         .dispatch(dptree::deps![
@@ -0,0 +1,423 @@
+use anyhow::{Result, bail};
+use exif::Reader;
+use once_cell::sync::Lazy;
+use regex::bytes::{Captures, Regex};
+use std::{io::Cursor, path::Path};
+
+const MAKE: u16 = 0x010F;
+const ORIENTATION: u16 = 0x0112;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const MAKER_NOTE: u16 = 0x927C;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Tags which tell who the author is or which device they own, camera settings are not here
+const PRIVATE_TAGS: &[(u16, &str)] = &[
+    (0x013B, "Artist"),
+    (0xA420, "ImageUniqueID"),
+    (0xA430, "CameraOwnerName"),
+    (0xA431, "BodySerialNumber"),
+    (0xA435, "LensSerialNumber"),
+];
+
+/// Same kind of tags inside maker notes, the rest of a note keeps camera settings and stays as is
+const CANON_PRIVATE_TAGS: &[(u16, &str)] = &[(0x0009, "OwnerName"), (0x000C, "SerialNumber"), (0x0096, "InternalSerialNumber")];
+const NIKON_PRIVATE_TAGS: &[(u16, &str)] = &[(0x001D, "SerialNumber"), (0x00A0, "SerialNumber")];
+const NIKON_HEADER: &[u8] = b"Nikon\0";
+
+const PRIVATE_XMP: &str = "SerialNumber|BodySerialNumber|LensSerialNumber|InternalSerialNumber|CameraOwnerName|OwnerName";
+const GPS_XMP: &str = "GPS[A-Za-z]*";
+
+static XMP_ATTRIBUTE: Lazy<Regex> = Lazy::new(|| Regex::new(&format!(r#"\s(\w+:(?:{PRIVATE_XMP}|{GPS_XMP}))="([^"]*)""#)).unwrap());
+static XMP_ELEMENT: Lazy<Regex> = Lazy::new(|| Regex::new(&format!(r"<(\w+:(?:{PRIVATE_XMP}|{GPS_XMP}))>([^<]*)</")).unwrap());
+
+/// Removes private metadata from the file in place and returns names of removed fields.
+/// Values are zeroed without rebuilding Exif and XMP blocks, so offsets used by the container stay valid
+/// and the same code works for JPEG, HEIC and PNG
+pub fn sanitize(path: &Path, keep_gps: bool) -> Result<Vec<String>> {
+    let mut data = std::fs::read(path)?;
+    let removed = sanitize_bytes(&mut data, keep_gps)?;
+
+    if !removed.is_empty() {
+        std::fs::write(path, data)?;
+    }
+
+    Ok(removed)
+}
+
+/// Same as [`sanitize`] but leaves the file untouched, used to show moderators what will be removed
+pub fn find_private(path: &Path) -> Result<Vec<String>> {
+    let mut data = std::fs::read(path)?;
+
+    sanitize_bytes(&mut data, false)
+}
+
+/// Sets Orientation of the raw TIFF block to normal, used when pixels have already been rotated by the decoder
+pub fn reset_orientation(tiff: &mut [u8]) -> Result<()> {
+    let mut block = Tiff::new(tiff)?;
+    let ifd0 = block.read_u32(4)? as usize;
+
+    if let Some(entry) = block.find_entry(ifd0, ORIENTATION)? {
+        block.write_u16(entry + 8, 1)?;
+    }
+
+    Ok(())
+}
+
+fn sanitize_bytes(data: &mut [u8], keep_gps: bool) -> Result<Vec<String>> {
+    let mut removed = sanitize_exif(data, keep_gps)?;
+
+    removed.extend(sanitize_xmp(data, keep_gps));
+
+    if !removed.is_empty() && data.starts_with(PNG_SIGNATURE) {
+        fix_png_crc(data)?;
+    }
+
+    Ok(removed)
+}
+
+fn sanitize_exif(data: &mut [u8], keep_gps: bool) -> Result<Vec<String>> {
+    let tiff = match Reader::new().read_from_container(&mut Cursor::new(&*data)) {
+        Ok(e) => e.buf().to_vec(),
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let start = match data.windows(tiff.len().min(64)).position(|w| w == &tiff[..w.len()]) {
+        Some(s) if data.len() >= s + tiff.len() && data[s..s + tiff.len()] == tiff[..] => s,
+        _ => bail!("Can't locate Exif block"),
+    };
+
+    let mut block = Tiff::new(&mut data[start..start + tiff.len()])?;
+    let mut removed = Vec::new();
+    let ifd0 = block.read_u32(4)? as usize;
+    let mut ifds = vec![ifd0];
+
+    if let Some(entry) = block.find_entry(ifd0, EXIF_IFD_POINTER)? {
+        ifds.push(block.read_u32(entry + 8)? as usize);
+    }
+
+    for ifd in &ifds {
+        removed.extend(block.clear_tags(*ifd, PRIVATE_TAGS)?);
+    }
+
+    if let Some(exif_ifd) = ifds.get(1)
+        && let Some(entry) = block.find_entry(*exif_ifd, MAKER_NOTE)?
+    {
+        let is_canon = match block.find_entry(ifd0, MAKE)? {
+            Some(make) => block.value(make)?.starts_with(b"Canon"),
+            None => false,
+        };
+
+        removed.extend(sanitize_maker_note(&mut block, entry, is_canon)?);
+    }
+
+    if !keep_gps && let Some(entry) = block.find_entry(ifd0, GPS_IFD_POINTER)? {
+        let gps_ifd = block.read_u32(entry + 8)? as usize;
+
+        if block.read_u16(gps_ifd)? > 0 {
+            block.clear_ifd(gps_ifd)?;
+            removed.push("GPS".to_string());
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Clears serial numbers and owner names inside maker notes of known formats.
+/// Canon notes are a plain IFD with offsets from the Exif block, Nikon ones carry their own TIFF header
+fn sanitize_maker_note(block: &mut Tiff<&mut [u8]>, entry: usize, is_canon: bool) -> Result<Vec<String>> {
+    let offset = block.value_offset(entry)?;
+    let note = block.value(entry)?;
+
+    if note.starts_with(NIKON_HEADER) && note.len() > 10 {
+        let start = offset + 10;
+        let end = offset + note.len();
+        let mut nikon = Tiff::new(&mut block.data[start..end])?;
+        let ifd = nikon.read_u32(4)? as usize;
+
+        return nikon.clear_tags(ifd, NIKON_PRIVATE_TAGS);
+    }
+
+    if is_canon {
+        return block.clear_tags(offset, CANON_PRIVATE_TAGS);
+    }
+
+    Ok(Vec::new())
+}
+
+/// Blanks values of private XMP properties with spaces, so the packet keeps its length.
+/// Only plain values are touched, nested structures are left as is
+fn sanitize_xmp(data: &mut [u8], keep_gps: bool) -> Vec<String> {
+    let mut removed = Vec::new();
+    let mut ranges = Vec::new();
+    let mut collect = |caps: Captures| {
+        let name = String::from_utf8_lossy(&caps[1]).to_string();
+        let value = caps.get(2).unwrap();
+
+        if value.as_bytes().iter().all(|b| b.is_ascii_whitespace()) || (keep_gps && name.contains(":GPS")) {
+            return;
+        }
+
+        ranges.push(value.range());
+
+        if !removed.contains(&name) {
+            removed.push(name);
+        }
+    };
+
+    XMP_ATTRIBUTE.captures_iter(data).for_each(&mut collect);
+    XMP_ELEMENT.captures_iter(data).for_each(&mut collect);
+
+    for range in ranges {
+        data[range].fill(b' ');
+    }
+
+    removed
+}
+
+/// PNG chunks are checksummed, so they have to be recalculated after patching
+fn fix_png_crc(data: &mut [u8]) -> Result<()> {
+    let mut offset = PNG_SIGNATURE.len();
+
+    while offset + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into()?) as usize;
+        let end = offset + 8 + length;
+
+        if end + 4 > data.len() {
+            bail!("PNG chunk is out of bounds");
+        }
+
+        let crc = crc32fast::hash(&data[offset + 4..end]);
+
+        data[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+        offset = end + 4;
+    }
+
+    Ok(())
+}
+
+/// Minimal reader of TIFF structures, writing is available when the data is mutable
+pub struct Tiff<T> {
+    data: T,
+    little_endian: bool,
+}
+
+impl<T: AsRef<[u8]>> Tiff<T> {
+    pub fn new(data: T) -> Result<Self> {
+        let little_endian = match data.as_ref().get(..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => bail!("Unknown TIFF byte order"),
+        };
+
+        Ok(Self { data, little_endian })
+    }
+
+    pub fn read_u16(&self, offset: usize) -> Result<u16> {
+        let bytes: [u8; 2] = self.bytes(offset, 2)?.try_into()?;
+
+        Ok(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    pub fn read_u32(&self, offset: usize) -> Result<u32> {
+        let bytes: [u8; 4] = self.bytes(offset, 4)?.try_into()?;
+
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    pub fn bytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        match self.data.as_ref().get(offset..offset + len) {
+            Some(b) => Ok(b),
+            None => bail!("TIFF offset {offset} is out of bounds"),
+        }
+    }
+
+    /// Returns the offset of the entry with the tag in the IFD
+    pub fn find_entry(&self, ifd: usize, tag: u16) -> Result<Option<usize>> {
+        let count = self.read_u16(ifd)? as usize;
+
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+
+            if self.read_u16(entry)? == tag {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads integer values of the entry, other types are not needed
+    pub fn read_values(&self, entry: usize) -> Result<Vec<u32>> {
+        let field_type = self.read_u16(entry + 2)?;
+        let count = self.read_u32(entry + 4)? as usize;
+        let size = type_size(field_type);
+        let offset = if size * count > 4 {
+            self.read_u32(entry + 8)? as usize
+        } else {
+            entry + 8
+        };
+
+        (0..count)
+            .map(|i| match size {
+                2 => self.read_u16(offset + i * 2).map(u32::from),
+                4 => self.read_u32(offset + i * 4),
+                _ => self.bytes(offset + i, 1).map(|b| u32::from(b[0])),
+            })
+            .collect()
+    }
+
+    /// Offset of the value of the entry, short values are kept in the entry itself
+    pub fn value_offset(&self, entry: usize) -> Result<usize> {
+        let size = self.value_size(entry)?;
+
+        if size > 4 {
+            Ok(self.read_u32(entry + 8)? as usize)
+        } else {
+            Ok(entry + 8)
+        }
+    }
+
+    /// Raw bytes of the value of the entry
+    pub fn value(&self, entry: usize) -> Result<&[u8]> {
+        self.bytes(self.value_offset(entry)?, self.value_size(entry)?)
+    }
+
+    fn value_size(&self, entry: usize) -> Result<usize> {
+        Ok(type_size(self.read_u16(entry + 2)?) * self.read_u32(entry + 4)? as usize)
+    }
+
+    /// Offset of the IFD which follows the given one, `None` for the last one
+    pub fn next_ifd(&self, ifd: usize) -> Result<Option<usize>> {
+        let count = self.read_u16(ifd)? as usize;
+        let next = self.read_u32(ifd + 2 + count * 12)? as usize;
+
+        Ok((next != 0).then_some(next))
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Tiff<T> {
+    fn write_u16(&mut self, offset: usize, value: u16) -> Result<()> {
+        let bytes = if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+
+        match self.data.as_mut().get_mut(offset..offset + 2) {
+            Some(b) => {
+                b.copy_from_slice(&bytes);
+                Ok(())
+            }
+            None => bail!("TIFF offset {offset} is out of bounds"),
+        }
+    }
+
+    fn zero(&mut self, offset: usize, len: usize) -> Result<()> {
+        match self.data.as_mut().get_mut(offset..offset + len) {
+            Some(b) => {
+                b.fill(0);
+                Ok(())
+            }
+            None => bail!("TIFF offset {offset} is out of bounds"),
+        }
+    }
+
+    /// Zeroes the value of the entry, the entry itself stays so the IFD keeps its layout.
+    /// Returns `false` when the value is already empty
+    fn clear_entry(&mut self, entry: usize) -> Result<bool> {
+        let size = self.value_size(entry)?;
+        let offset = self.value_offset(entry)?;
+
+        if self.bytes(offset, size)?.iter().all(|b| *b == 0) {
+            return Ok(false);
+        }
+
+        self.zero(offset, size)?;
+
+        Ok(true)
+    }
+
+    /// Zeroes values of the tags found in the IFD and returns names of the cleared ones
+    fn clear_tags(&mut self, ifd: usize, tags: &[(u16, &str)]) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+
+        for (tag, name) in tags {
+            if let Some(entry) = self.find_entry(ifd, *tag)?
+                && self.clear_entry(entry)?
+                && !removed.iter().any(|r| r == name)
+            {
+                removed.push(name.to_string());
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Zeroes values of every entry and leaves an empty IFD, which is still valid for readers
+    fn clear_ifd(&mut self, ifd: usize) -> Result<()> {
+        let count = self.read_u16(ifd)? as usize;
+
+        for i in 0..count {
+            self.clear_entry(ifd + 2 + i * 12)?;
+        }
+
+        self.zero(ifd, 2 + count * 12 + 4)
+    }
+}
+
+fn type_size(field_type: u16) -> usize {
+    match field_type {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_entry(data: &mut [u8], at: usize, tag: u16, field_type: u16, count: u32, value: u32) {
+        data[at..at + 2].copy_from_slice(&tag.to_le_bytes());
+        data[at + 2..at + 4].copy_from_slice(&field_type.to_le_bytes());
+        data[at + 4..at + 8].copy_from_slice(&count.to_le_bytes());
+        data[at + 8..at + 12].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// TIFF made by a Canon with a maker note holding a setting and the serial number
+    fn canon_tiff() -> Vec<u8> {
+        let mut data = vec![0; 0x108];
+
+        data[..8].copy_from_slice(b"II\x2a\x00\x08\x00\x00\x00");
+        data[8] = 2;
+        put_entry(&mut data, 10, MAKE, 2, 6, 0x100);
+        put_entry(&mut data, 22, EXIF_IFD_POINTER, 4, 1, 0x40);
+        data[0x40] = 1;
+        put_entry(&mut data, 0x42, MAKER_NOTE, 7, 30, 0x80);
+        data[0x80] = 2;
+        put_entry(&mut data, 0x82, 0x0004, 3, 1, 7);
+        put_entry(&mut data, 0x8E, 0x000C, 4, 1, 0x12345678);
+        data[0x100..0x106].copy_from_slice(b"Canon\0");
+
+        data
+    }
+
+    #[test]
+    fn clears_only_serial_in_maker_note() {
+        let mut data = canon_tiff();
+
+        assert_eq!(sanitize_bytes(&mut data, false).unwrap(), vec!["SerialNumber"]);
+
+        let block = Tiff::new(&data[..]).unwrap();
+
+        assert_eq!(block.read_values(0x82).unwrap(), vec![7]);
+        assert_eq!(block.read_values(0x8E).unwrap(), vec![0]);
+        assert_eq!(block.read_u16(0x42).unwrap(), MAKER_NOTE);
+    }
+}
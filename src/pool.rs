@@ -0,0 +1,178 @@
+use envconfig::Envconfig;
+use once_cell::sync::OnceCell;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+pub static INSTANCE: OnceCell<ImagePool> = OnceCell::new();
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ImagePoolConfig {
+    #[envconfig(from = "IMAGE_WORKERS", default = "2")]
+    pub workers: usize,
+}
+
+/// Runs decoding, resizing and file copies on blocking threads, so they don't stall the async runtime.
+/// Big photos take hundreds of megabytes decoded, so only `workers` jobs run at once
+pub struct ImagePool {
+    permits: Arc<Semaphore>,
+}
+
+impl ImagePool {
+    pub fn new(config: &ImagePoolConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.workers.max(1))),
+        }
+    }
+
+    pub fn global() -> &'static ImagePool {
+        INSTANCE.get().expect("Image pool is not initialized")
+    }
+
+    /// Waits for a free worker and runs the job. The permit is moved into the job,
+    /// so it's held until the job is done even when the caller stops waiting
+    pub async fn run<F, T>(&self, job: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = Arc::clone(&self.permits).acquire_owned().await?;
+
+        Ok(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+
+            job()
+        })
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode_heif;
+    use crate::test_utils::{HEIC_FIXTURE, TempFile};
+    use std::path::Path;
+    use std::{
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+    use tokio::task::JoinSet;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn pool(workers: usize) -> Arc<ImagePool> {
+        Arc::new(ImagePool::new(&ImagePoolConfig { workers }))
+    }
+
+    /// Runs the work next to a task which sleeps for 10 ms in a loop, returns how many times it woke up,
+    /// how late it was at worst and how long the work took
+    async fn with_ticker<F: Future<Output = ()>>(work: F) -> (u64, Duration, Duration) {
+        let ticks = Arc::new(AtomicU64::new(0));
+        let worst = Arc::new(AtomicU64::new(0));
+        let ticker = tokio::spawn({
+            let ticks = Arc::clone(&ticks);
+            let worst = Arc::clone(&worst);
+
+            async move {
+                loop {
+                    let started = Instant::now();
+
+                    tokio::time::sleep(TICK).await;
+                    worst.fetch_max(started.elapsed().as_millis() as u64, Ordering::SeqCst);
+                    ticks.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        let started = Instant::now();
+
+        work.await;
+
+        let elapsed = started.elapsed();
+        ticker.abort();
+
+        (ticks.load(Ordering::SeqCst), Duration::from_millis(worst.load(Ordering::SeqCst)), elapsed)
+    }
+
+    /// A single-threaded runtime stalls completely when a task blocks, like a dispatcher would during a conversion.
+    /// With every worker busy and more jobs waiting, the ticker must still wake up about on time
+    fn assert_runtime_kept_going((ticks, worst, elapsed): (u64, Duration, Duration)) {
+        let expected = (elapsed.as_millis() / TICK.as_millis()) as u64;
+
+        assert!(worst < TICK * 10, "ticker has been late for {worst:?}");
+        assert!(ticks * 3 >= expected, "only {ticks} ticks in {elapsed:?}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn runtime_keeps_going_while_jobs_run() {
+        let pool = pool(2);
+        let mut jobs = JoinSet::new();
+
+        for _ in 0..6 {
+            let pool = Arc::clone(&pool);
+
+            jobs.spawn(async move { pool.run(|| std::thread::sleep(Duration::from_millis(100))).await.unwrap() });
+        }
+
+        let result = with_ticker(async {
+            while let Some(result) = jobs.join_next().await {
+                result.unwrap();
+            }
+        })
+        .await;
+
+        assert_runtime_kept_going(result);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn runtime_keeps_going_while_heic_is_converted() {
+        let pool = pool(1);
+        let files: Vec<TempFile> = (0..3).map(|_| TempFile::new("jpg")).collect();
+        let mut jobs = JoinSet::new();
+
+        for file in &files {
+            let pool = Arc::clone(&pool);
+            let path = file.0.clone();
+
+            jobs.spawn(async move { pool.run(move || decode_heif(Path::new(HEIC_FIXTURE), &path)).await.unwrap() });
+        }
+
+        let result = with_ticker(async {
+            while let Some(result) = jobs.join_next().await {
+                result.unwrap().unwrap();
+            }
+        })
+        .await;
+
+        assert_runtime_kept_going(result);
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_jobs() {
+        let pool = pool(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let mut jobs = JoinSet::new();
+
+        for _ in 0..6 {
+            let pool = Arc::clone(&pool);
+            let running = Arc::clone(&running);
+            let most = Arc::clone(&most);
+
+            jobs.spawn(async move {
+                pool.run(move || {
+                    most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+                .await
+                .unwrap();
+            });
+        }
+
+        while let Some(result) = jobs.join_next().await {
+            result.unwrap();
+        }
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+}
@@ -3,7 +3,7 @@ use crate::redis::subscriber::MessageHandler;
 use backon::{ConstantBuilder, Retryable};
 use envconfig::Envconfig;
 use once_cell::sync::OnceCell;
-use redis::{AsyncCommands, Client as RedisClient, aio::MultiplexedConnection};
+use redis::{AsyncCommands, Client as RedisClient, ExistenceCheck, SetExpiry, SetOptions, aio::MultiplexedConnection};
 use redis_work_queue::{Item, KeyPrefix, WorkQueue};
 use serde::{Serialize, de::DeserializeOwned};
 use serde_json::{Value, json};
@@ -42,13 +42,17 @@ impl RedisManager {
         INSTANCE.get().expect("RedisManager is not initialized")
     }
 
-    pub async fn add_queue_item(&self, item: &Value) {
+    /// Returns false when the item hasn't been queued
+    pub async fn add_queue_item(&self, item: &Value) -> bool {
         let json_item = Item::from_string_data(item.to_string());
         let mut con = self.get_async_connection().await;
 
         match self.queue.add_item(&mut con, &json_item).await {
-            Ok(_) => (),
-            Err(e) => error!("Can't add queue: {e}"),
+            Ok(_) => true,
+            Err(e) => {
+                error!("Can't add queue: {e}");
+                false
+            }
         }
     }
 
@@ -60,10 +64,9 @@ impl RedisManager {
     where
         T: DeserializeOwned,
     {
-        let ans: Option<String> = self.get_by_key(key).await;
-        let val = serde_json::from_str::<T>(&ans.unwrap());
+        let ans: String = self.get_by_key(key).await?;
 
-        val.ok()
+        serde_json::from_str::<T>(&ans).ok()
     }
 
     pub async fn set_model<T>(&self, key: &str, value: T) -> bool
@@ -85,6 +88,46 @@ impl RedisManager {
         conn.set(key, value).await.unwrap_or(false)
     }
 
+    pub async fn set_by_key_ex(&self, key: &str, value: &str, ttl_secs: u64) -> bool {
+        let mut conn = self.get_async_connection().await;
+
+        conn.set_ex(key, value, ttl_secs).await.unwrap_or(false)
+    }
+
+    /// Increments a counter, the expiration is set only when the counter is created so the window doesn't slide.
+    /// Both commands go in one transaction, so the counter can't be left without expiration
+    pub async fn increment_ex(&self, key: &str, ttl_secs: u64) -> u64 {
+        let mut conn = self.get_async_connection().await;
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(ttl_secs));
+        let res: Result<(u64,), _> = redis::pipe()
+            .atomic()
+            .set_options(key, 0, options)
+            .ignore()
+            .incr(key, 1)
+            .query_async(&mut conn)
+            .await;
+
+        res.map(|(count,)| count).unwrap_or_else(|e| {
+            error!("Can't increment key {key}: {e}");
+            0
+        })
+    }
+
+    pub async fn get_ttl(&self, key: &str) -> Option<u64> {
+        let mut conn = self.get_async_connection().await;
+        let ttl: i64 = conn.ttl(key).await.ok()?;
+
+        u64::try_from(ttl).ok()
+    }
+
+    pub async fn delete_by_key(&self, key: &str) -> bool {
+        let mut conn = self.get_async_connection().await;
+
+        conn.del(key).await.unwrap_or(false)
+    }
+
     pub async fn subscriber(&self, bot_config: &BotConfig) {
         tokio::task::spawn({
             let mut con = self.get_async_connection().await;
@@ -108,6 +151,8 @@ impl RedisManager {
 
                         if let Err(e) = c {
                             error!("Error occurred while trying process message: {e}");
+
+                            handler.fail(&message).await;
                         }
 
                         queue.complete(&mut con, &item).await.expect("Can't complete message");
@@ -1,17 +1,22 @@
 use crate::bot::types::{FileType, PhotoToUpload};
 use crate::bot::{BotConfig, BotManager};
-use crate::db::entity::photos::Model;
+use crate::db::entity::photos::{Model, PhotoStatus};
 use crate::db::entity::prelude::Photos;
+use crate::pool::ImagePool;
 use crate::redis::types::QueueMessage;
+use crate::template::Vars;
 use crate::types::CanMention;
 use anyhow::Result;
 use teloxide::{
     prelude::*,
-    types::{InputFile, InputMedia, InputMediaDocument},
+    types::{InputFile, InputMedia, InputMediaDocument, InputMediaPhoto},
 };
+use uuid::Uuid;
 
 use super::types::QueueOperation;
 
+const MEDIA_GROUP_LIMIT: usize = 10;
+
 #[derive(Clone, Debug)]
 pub struct MessageHandler {
     bot_manager: BotManager,
@@ -26,6 +31,12 @@ impl MessageHandler {
 
     pub async fn handle(&self, message: &QueueMessage) -> Result<()> {
         if let Some(doc) = &Photos::get_by_id(message.id).await {
+            if doc.status == PhotoStatus::Withdrawn {
+                info!("Photo {} has been withdrawn by the author, skipping", doc.uuid);
+
+                return Ok(());
+            }
+
             match message.operation {
                 QueueOperation::Approve => {
                     self.approve(doc).await?;
@@ -41,77 +52,198 @@ impl MessageHandler {
         Ok(())
     }
 
-    async fn approve(&self, model: &Model) -> Result<()> {
-        let bot = self.bot_manager.get_bot();
+    // Called when all retries of the message are exhausted
+    pub async fn fail(&self, message: &QueueMessage) {
+        if let QueueOperation::Approve = message.operation
+            && let Some(model) = Photos::get_by_id(message.id).await
+            && !model.fail().await
+        {
+            error!("Can't mark photo {} as failed", model.uuid);
+        }
+    }
 
-        if model.is_approved {
-            error!("Photo already approved {}", &model.uuid);
+    async fn approve(&self, model: &Model) -> Result<()> {
+        if !model.is_ready() {
+            error!("Photo {} can't be posted in status {:?}", &model.uuid, model.status);
 
             return Ok(());
         }
 
-        let file_type = FileType::from(&model.mime_type);
-        let photo_to_upload = PhotoToUpload::new(&file_type);
-        let original_path = photo_to_upload.document_path();
-
-        if let Err(e) = self.bot_manager.download_doc(&model.file_id, original_path).await {
-            error!("Error occurred: {e:?}");
+        if let Some(submission) = model.submission().await {
+            let album: Vec<Model> = submission.photos().await.into_iter().filter(|p| p.is_ready()).collect();
 
-            return Ok(());
+            if album.len() > 1 {
+                return self.approve_album(&album).await;
+            }
         }
 
-        if let Err(e) = photo_to_upload.convert() {
-            error!("Error occurred: {e:?}");
+        let bot = self.bot_manager.get_bot();
+        let (photo_to_upload, exif) = match self.prepare(model).await {
+            Some(p) => p,
+            None => {
+                model.fail().await;
 
-            return Ok(());
-        }
+                return Ok(());
+            }
+        };
 
+        let file_type = photo_to_upload.file_type();
+        let original_path = photo_to_upload.document_path();
         let photo_path = photo_to_upload.photo();
         let original_converted_path = photo_to_upload.converted();
         let thumb_path = photo_to_upload.thumbnail();
-        let mut captions = photo_to_upload.get_exif_info();
-        captions.push(format!("👤 Автор: {}", model.user().await.mention_or_url()));
+        let caption = BotManager::build_post_caption(model, exif, &model.user().await.mention_or_url());
 
         let original = InputFile::file(original_path).file_name(format!("original.{}", file_type.get_extension()));
         let original_converted = InputFile::file(original_converted_path).file_name("converted_original.jpg");
         let photo = InputFile::file(photo_path);
         let thumb = InputFile::file(thumb_path);
 
-        let msg = bot
-            .send_photo(ChatId(self.bot_manager.get_group_id()), photo)
-            .caption(captions.join("\n"))
-            .await?;
+        let msg = bot.send_photo(ChatId(self.bot_manager.get_group_id()), photo).caption(caption).await?;
 
-        match file_type {
-            FileType::Heic => {
-                bot.send_media_group(
-                    ChatId(self.bot_manager.get_group_id()),
-                    vec![
-                        InputMedia::Document(InputMediaDocument::new(original).thumbnail(thumb)),
-                        InputMedia::Document(InputMediaDocument::new(original_converted)),
-                    ],
-                )
+        if file_type.needs_conversion() {
+            bot.send_media_group(
+                ChatId(self.bot_manager.get_group_id()),
+                vec![
+                    InputMedia::Document(InputMediaDocument::new(original).thumbnail(thumb)),
+                    InputMedia::Document(InputMediaDocument::new(original_converted)),
+                ],
+            )
+            .await?;
+        } else {
+            bot.send_document(ChatId(self.bot_manager.get_group_id()), original)
+                .thumbnail(thumb)
                 .await?;
+        }
+
+        model.mark_posted(msg.id.0).await;
+
+        self.notify_posted(model.user_id, msg.id.0).await;
+
+        Ok(())
+    }
+
+    async fn approve_album(&self, models: &[Model]) -> Result<()> {
+        let bot = self.bot_manager.get_bot();
+        let mut prepared: Vec<(&Model, FileType, PhotoToUpload, Vars)> = Vec::with_capacity(models.len());
+
+        for model in models {
+            match self.prepare(model).await {
+                Some((p, exif)) => prepared.push((model, p.file_type(), p, exif)),
+                None => {
+                    model.fail().await;
+                }
             }
-            _ => {
-                bot.send_document(ChatId(self.bot_manager.get_group_id()), original)
-                    .thumbnail(thumb)
-                    .await?;
+        }
+
+        if prepared.len() < 2 {
+            error!("Not enough photos to post an album");
+
+            return Ok(());
+        }
+
+        let author = models[0].user().await.mention_or_url();
+        let mut photos: Vec<InputMedia> = Vec::with_capacity(prepared.len());
+        let mut documents: Vec<InputMedia> = Vec::with_capacity(prepared.len() * 2);
+
+        for (i, (model, file_type, photo_to_upload, exif)) in prepared.iter().enumerate() {
+            let caption = BotManager::build_post_caption(model, exif.clone(), &author);
+
+            photos.push(InputMedia::Photo(
+                InputMediaPhoto::new(InputFile::file(photo_to_upload.photo())).caption(caption),
+            ));
+
+            let original = InputFile::file(photo_to_upload.document_path()).file_name(format!("original_{}.{}", i + 1, file_type.get_extension()));
+            let thumb = InputFile::file(photo_to_upload.thumbnail());
+
+            documents.push(InputMedia::Document(InputMediaDocument::new(original).thumbnail(thumb)));
+
+            if file_type.needs_conversion() {
+                let converted = InputFile::file(photo_to_upload.converted()).file_name(format!("converted_original_{}.jpg", i + 1));
+
+                documents.push(InputMedia::Document(InputMediaDocument::new(converted)));
             }
         }
 
-        if !photo_to_upload.delete_all() {
-            warn!("Not all files have been deleted!")
+        let messages = bot.send_media_group(ChatId(self.bot_manager.get_group_id()), photos).await?;
+
+        // Media group holds up to 10 items, split evenly so that no group is left with a single document
+        let chunks = documents.len().div_ceil(MEDIA_GROUP_LIMIT);
+        let chunk_size = documents.len().div_ceil(chunks);
+
+        for chunk in documents.chunks(chunk_size) {
+            bot.send_media_group(ChatId(self.bot_manager.get_group_id()), chunk.to_vec()).await?;
+        }
+
+        for ((model, _, _, _), msg) in prepared.iter().zip(messages.iter()) {
+            model.mark_posted(msg.id.0).await;
         }
 
-        model.approve(msg.id.0).await;
+        if let Some(msg) = messages.first() {
+            self.notify_posted(models[0].user_id, msg.id.0).await;
+        }
 
         Ok(())
     }
 
+    async fn notify_posted(&self, user_id: i64, msg_id: i32) {
+        let text = t!("messages.photo_was_posted", url = self.bot_manager.get_post_url(msg_id as i64));
+
+        if let Err(e) = self.bot_manager.get_bot().send_message(ChatId(user_id), text).await {
+            warn!("Can't notify user {user_id} about the post: {e}");
+        }
+    }
+
+    /// Downloads and processes the photo, returns its files with EXIF values for the caption
+    async fn prepare(&self, model: &Model) -> Option<(PhotoToUpload, Vars)> {
+        let photo_to_upload = match PhotoToUpload::new(&FileType::from(&model.mime_type)) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Can't create job directory for photo {}: {e}", model.uuid);
+
+                return None;
+            }
+        };
+
+        if let Err(e) = self.bot_manager.download_doc(&model.file_id, photo_to_upload.document_path()).await {
+            error!("Error occurred: {e:?}");
+
+            return None;
+        }
+
+        let uuid = model.uuid;
+        let publish_location = model.publish_location;
+        let signature = if model.watermark {
+            let user = model.user().await;
+
+            Some(user.username.map(|n| format!("@{n}")).unwrap_or(user.firstname))
+        } else {
+            None
+        };
+
+        match ImagePool::global()
+            .run(move || process(photo_to_upload, uuid, publish_location, signature))
+            .await
+        {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Can't process photo {uuid}: {e}");
+
+                None
+            }
+        }
+    }
+
     async fn decline(&self, model: &Model, reason: &Option<String>) -> Result<()> {
         let bot = self.bot_manager.get_bot();
 
+        // The photo is declined by the moderator right away, only the author is left to notify
+        if model.status != PhotoStatus::Declined {
+            error!("Photo {} can't be declined in status {:?}", &model.uuid, model.status);
+
+            return Ok(());
+        }
+
         if let Some(r) = reason {
             bot.send_message(ChatId(model.user_id), t!("messages.photo_was_declined_by_reason", reason = r))
                 .await?;
@@ -122,3 +254,41 @@ impl MessageHandler {
         Ok(())
     }
 }
+
+/// Blocking part of [`MessageHandler::prepare`], runs on the image pool
+fn process(mut photo_to_upload: PhotoToUpload, uuid: Uuid, publish_location: bool, signature: Option<String>) -> Option<(PhotoToUpload, Vars)> {
+    if photo_to_upload.detect_type().is_none() {
+        error!("Photo {uuid} is not a supported image");
+
+        return None;
+    }
+
+    if let Err(e) = photo_to_upload.convert() {
+        error!("Error occurred: {e:?}");
+
+        return None;
+    }
+
+    // Better not to post the photo at all than leak where the author lives or which devices they own
+    match photo_to_upload.sanitize(publish_location) {
+        Ok(removed) if !removed.is_empty() => info!("Removed from photo {uuid}: {}", removed.join(", ")),
+        Ok(_) => {}
+        Err(e) => {
+            error!("Can't remove private metadata from photo {uuid}: {e}");
+
+            return None;
+        }
+    }
+
+    if let Err(e) = photo_to_upload.check(signature.as_deref()) {
+        error!("Photo {uuid} can't be sent: {e:?}");
+
+        return None;
+    }
+
+    photo_to_upload.make_thumbnail();
+
+    let exif = photo_to_upload.get_exif_vars();
+
+    Some((photo_to_upload, exif))
+}
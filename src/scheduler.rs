@@ -0,0 +1,186 @@
+use crate::db::entity::prelude::Photos;
+use crate::redis::{RedisManager, types::QueueMessage};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use envconfig::Envconfig;
+use serde_json::json;
+use std::time::Duration;
+
+const LAST_RELEASE_KEY: &str = "scheduler_last_release";
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct SchedulerConfig {
+    /// Comma separated list of local publishing times, e.g. `09:00,14:30,19:00`.
+    /// When empty, photos are released as soon as the minimal gap allows.
+    #[envconfig(from = "PUBLISH_SLOTS", default = "")]
+    pub slots: String,
+    #[envconfig(from = "PUBLISH_MIN_GAP_MINUTES", default = "60")]
+    pub min_gap_minutes: i64,
+    /// IANA name of the timezone of the slots, e.g. `Europe/Moscow`
+    #[envconfig(from = "PUBLISH_TIMEZONE", default = "Europe/Moscow")]
+    pub timezone: String,
+    #[envconfig(from = "PUBLISH_CHECK_INTERVAL_SECONDS", default = "30")]
+    pub check_interval: u64,
+}
+
+pub struct Scheduler {
+    slots: Vec<NaiveTime>,
+    min_gap: ChronoDuration,
+    timezone: Tz,
+    check_interval: Duration,
+}
+
+impl Scheduler {
+    pub fn new(config: &SchedulerConfig) -> Self {
+        let mut slots: Vec<NaiveTime> = config
+            .slots
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| match NaiveTime::parse_from_str(s, "%H:%M") {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    error!("Can't parse publishing slot \"{s}\": {e}");
+                    None
+                }
+            })
+            .collect();
+
+        slots.sort();
+        slots.dedup();
+
+        Self {
+            slots,
+            min_gap: ChronoDuration::try_minutes(config.min_gap_minutes)
+                .filter(|gap| *gap >= ChronoDuration::zero())
+                .expect("PUBLISH_MIN_GAP_MINUTES is out of range"),
+            timezone: config.timezone.parse().expect("Can't parse PUBLISH_TIMEZONE"),
+            check_interval: Duration::from_secs(config.check_interval.max(1)),
+        }
+    }
+
+    pub fn start(self) {
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(self.check_interval);
+
+            loop {
+                interval.tick().await;
+                self.tick(Utc::now()).await;
+            }
+        });
+    }
+
+    async fn tick(&self, now: DateTime<Utc>) {
+        let Some(slot) = self.current_slot(now) else {
+            return;
+        };
+
+        let redis = RedisManager::global();
+        let last_release = redis
+            .get_by_key(LAST_RELEASE_KEY)
+            .await
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|ts| DateTime::from_timestamp(ts, 0));
+
+        if let Some(last) = last_release
+            && (last >= slot || now - last < self.min_gap)
+        {
+            return;
+        }
+
+        if let Some(photo) = Photos::get_next_ready().await {
+            info!("Releasing photo {} for posting", photo.uuid);
+
+            // Without the photo in the queue the slot is still free, the next tick will try again
+            if redis.add_queue_item(&json!(QueueMessage::approve(photo.uuid))).await {
+                redis.set_by_key(LAST_RELEASE_KEY, &now.timestamp().to_string()).await;
+            }
+        }
+    }
+
+    /// Returns the start of the latest slot that is not in the future.
+    /// Without configured slots every moment is a slot.
+    fn current_slot(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.slots.is_empty() {
+            return Some(now);
+        }
+
+        let local_now = now.with_timezone(&self.timezone).naive_local();
+        let today = local_now.date();
+
+        let local_slot = match self.slots.iter().rev().find(|t| **t <= local_now.time()) {
+            Some(t) => today.and_time(*t),
+            None => today.pred_opt()?.and_time(*self.slots.last()?),
+        };
+
+        self.to_utc(local_slot)
+    }
+
+    /// A slot skipped by a DST transition starts an hour later, a repeated one starts the first time
+    fn to_utc(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        self.timezone
+            .from_local_datetime(&local)
+            .earliest()
+            .or_else(|| self.timezone.from_local_datetime(&(local + ChronoDuration::hours(1))).earliest())
+            .map(|t| t.with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SchedulerConfig {
+        SchedulerConfig {
+            slots: String::new(),
+            min_gap_minutes: 60,
+            timezone: "UTC".to_string(),
+            check_interval: 30,
+        }
+    }
+
+    fn scheduler(slots: &str, timezone: &str) -> Scheduler {
+        Scheduler::new(&SchedulerConfig {
+            slots: slots.to_string(),
+            timezone: timezone.to_string(),
+            ..config()
+        })
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn picks_latest_local_slot() {
+        let scheduler = scheduler("09:00,19:00", "Europe/Moscow");
+
+        assert_eq!(scheduler.current_slot(utc("2026-10-18T10:00:00Z")), Some(utc("2026-10-18T06:00:00Z")));
+        assert_eq!(scheduler.current_slot(utc("2026-10-18T05:00:00Z")), Some(utc("2026-10-17T16:00:00Z")));
+    }
+
+    #[test]
+    fn follows_daylight_saving_time() {
+        let scheduler = scheduler("09:00", "Europe/Berlin");
+
+        assert_eq!(scheduler.current_slot(utc("2026-07-01T12:00:00Z")), Some(utc("2026-07-01T07:00:00Z")));
+        assert_eq!(scheduler.current_slot(utc("2026-12-01T12:00:00Z")), Some(utc("2026-12-01T08:00:00Z")));
+    }
+
+    #[test]
+    fn moves_skipped_slot_to_the_next_hour() {
+        // Clocks in Berlin jump from 02:00 to 03:00 on 2026-03-29
+        let scheduler = scheduler("02:30", "Europe/Berlin");
+
+        assert_eq!(scheduler.current_slot(utc("2026-03-29T10:00:00Z")), Some(utc("2026-03-29T01:30:00Z")));
+    }
+
+    #[test]
+    #[should_panic(expected = "PUBLISH_MIN_GAP_MINUTES")]
+    fn rejects_negative_gap() {
+        Scheduler::new(&SchedulerConfig {
+            min_gap_minutes: -1,
+            ..config()
+        });
+    }
+}
@@ -0,0 +1,169 @@
+use envconfig::Envconfig;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub static INSTANCE: OnceCell<Templates> = OnceCell::new();
+
+pub type Vars = HashMap<&'static str, String>;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TemplateConfig {
+    #[envconfig(from = "CAPTION_TEMPLATES_PATH")]
+    pub path: Option<String>,
+}
+
+/// Templates missing in the file fall back to the ones from locales
+#[derive(Deserialize, Default)]
+struct TemplateFile {
+    post: Option<String>,
+    card: Option<String>,
+    watermark: Option<String>,
+}
+
+/// Captions of channel posts and moderation cards, and the signature drawn on published photos
+pub struct Templates {
+    post: Template,
+    card: Template,
+    watermark: Template,
+}
+
+impl Templates {
+    pub fn new(config: &TemplateConfig) -> Self {
+        let file: TemplateFile = match &config.path {
+            Some(path) => toml::from_str(&std::fs::read_to_string(path).expect("Can't read templates file")).expect("Can't parse templates file"),
+            None => TemplateFile::default(),
+        };
+
+        let load = |source: Option<String>, key: &str| {
+            let source = source.unwrap_or_else(|| t!(key).to_string());
+
+            Template::parse(&source).unwrap_or_else(|e| panic!("Invalid template {key}: {e}"))
+        };
+
+        Self {
+            post: load(file.post, "templates.post"),
+            card: load(file.card, "templates.card"),
+            watermark: load(file.watermark, "templates.watermark"),
+        }
+    }
+
+    pub fn global() -> &'static Templates {
+        INSTANCE.get().expect("Templates are not initialized")
+    }
+
+    pub fn post(&self) -> &Template {
+        &self.post
+    }
+
+    pub fn card(&self) -> &Template {
+        &self.card
+    }
+
+    pub fn watermark(&self) -> &Template {
+        &self.watermark
+    }
+}
+
+/// Tiny template language for captions:
+/// `{name}` inserts a value, `{#name}...{/name}` is rendered only when the value is not empty
+/// and `{^name}...{/name}` only when it is empty. A section may list several names as `{#a|b}...{/a|b}`,
+/// then it is rendered when any of them is not empty. Anything else in braces is kept as is
+#[derive(Clone, Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Var(String),
+    Section { name: String, inverted: bool, nodes: Vec<Node> },
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut rest = source;
+        let nodes = parse_nodes(&mut rest, None)?;
+
+        Ok(Self { nodes })
+    }
+
+    pub fn render(&self, vars: &Vars) -> String {
+        let mut out = String::new();
+
+        render_nodes(&self.nodes, vars, &mut out);
+
+        out
+    }
+}
+
+fn parse_nodes(rest: &mut &str, section: Option<&str>) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        *rest = &rest[start..];
+
+        let tag = match rest.find('}') {
+            Some(end) if is_tag(&rest[1..end]) => &rest[1..end],
+            _ => {
+                text.push('{');
+                *rest = &rest[1..];
+                continue;
+            }
+        };
+        *rest = &rest[tag.len() + 2..];
+
+        if !text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut text)));
+        }
+
+        match tag.split_at(1) {
+            ("#", name) | ("^", name) => nodes.push(Node::Section {
+                name: name.to_string(),
+                inverted: tag.starts_with('^'),
+                nodes: parse_nodes(rest, Some(name))?,
+            }),
+            ("/", name) if section == Some(name) => return Ok(nodes),
+            ("/", name) => return Err(format!("Unexpected closing of section {name}")),
+            _ => nodes.push(Node::Var(tag.to_string())),
+        }
+    }
+
+    if let Some(name) = section {
+        return Err(format!("Section {name} is not closed"));
+    }
+
+    text.push_str(rest);
+    *rest = "";
+
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+
+    Ok(nodes)
+}
+
+fn is_tag(tag: &str) -> bool {
+    let name = tag.strip_prefix(['#', '^', '/']).unwrap_or(tag);
+
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '|')
+}
+
+fn render_nodes(nodes: &[Node], vars: &Vars, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(name) => out.push_str(vars.get(name.as_str()).map_or("", |v| v.as_str())),
+            Node::Section { name, inverted, nodes } => {
+                let present = name.split('|').any(|n| vars.get(n).is_some_and(|v| !v.is_empty()));
+
+                if present != *inverted {
+                    render_nodes(nodes, vars, out);
+                }
+            }
+        }
+    }
+}
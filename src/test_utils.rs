@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Sample from libheif-rs (CC BY-SA 4.0) with EXIF Orientation set to 6
+pub const HEIC_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/orientation_6.heic");
+
+/// Little-endian TIFF block with the only IFD0 entry: Orientation, the value is at bytes 18..20
+#[rustfmt::skip]
+pub fn tiff_with_orientation(orientation: u8) -> Vec<u8> {
+    vec![
+        b'I', b'I', 42, 0, 8, 0, 0, 0,
+        1, 0,
+        0x12, 0x01, 3, 0, 1, 0, 0, 0, orientation, 0, 0, 0,
+        0, 0, 0, 0,
+    ]
+}
+
+/// Path in the temp directory which is removed when the test is done
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(extension: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("{}.{extension}", Uuid::new_v4())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::image::{Corner, Image};
+use crate::template::{Templates, Vars};
+use ab_glyph::{Font, FontArc, Glyph, PxScale, ScaleFont, point};
+use envconfig::Envconfig;
+use image::{DynamicImage, Rgba, RgbaImage, imageops::FilterType};
+use once_cell::sync::OnceCell;
+
+pub static INSTANCE: OnceCell<Watermark> = OnceCell::new();
+
+const MIN_MARK_HEIGHT: u32 = 12;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct WatermarkConfig {
+    #[envconfig(from = "WATERMARK_LOGO_PATH")]
+    pub logo_path: Option<String>,
+    #[envconfig(from = "WATERMARK_FONT_PATH")]
+    pub font_path: Option<String>,
+    #[envconfig(from = "WATERMARK_CORNER", default = "bottom-right")]
+    pub corner: Corner,
+    /// Height of the mark relative to the shorter side of the photo
+    #[envconfig(from = "WATERMARK_SIZE", default = "0.05")]
+    pub size: f32,
+    #[envconfig(from = "WATERMARK_OPACITY", default = "0.6")]
+    pub opacity: f32,
+}
+
+/// Mark put on photos sent to the channel, originals stay untouched.
+/// The logo is used when it's set, otherwise the signature from the `watermark` template is written with the font.
+/// Without both of them watermarking is off
+pub struct Watermark {
+    logo: Option<RgbaImage>,
+    font: Option<FontArc>,
+    corner: Corner,
+    size: f32,
+    opacity: f32,
+}
+
+impl Watermark {
+    pub fn new(config: &WatermarkConfig) -> Self {
+        let logo = config
+            .logo_path
+            .as_ref()
+            .map(|path| image::open(path).expect("Can't read watermark logo").to_rgba8());
+        let font = config.font_path.as_ref().map(|path| {
+            let data = std::fs::read(path).expect("Can't read watermark font");
+
+            FontArc::try_from_vec(data).expect("Can't parse watermark font")
+        });
+
+        Self {
+            logo,
+            font,
+            corner: config.corner,
+            size: config.size.clamp(0.0, 1.0),
+            opacity: config.opacity.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn global() -> &'static Watermark {
+        INSTANCE.get().expect("Watermark is not initialized")
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.logo.is_some() || self.font.is_some()
+    }
+
+    /// Puts the mark on the image, returns whether anything has been drawn
+    pub fn apply(&self, img: &mut Image, author: &str) -> bool {
+        let (width, height) = img.get_size();
+        let mark = match self.render(width, height, author) {
+            Some(m) => m,
+            None => return false,
+        };
+
+        img.overlay(&mark, self.corner);
+
+        true
+    }
+
+    /// The mark takes at most a half of the photo width, so long signatures are made smaller
+    fn render(&self, width: u32, height: u32, author: &str) -> Option<RgbaImage> {
+        let mark_height = ((width.min(height) as f32 * self.size) as u32).max(MIN_MARK_HEIGHT);
+        let max_width = (width / 2).max(1);
+
+        let mut mark = if let Some(logo) = &self.logo {
+            DynamicImage::ImageRgba8(logo.clone())
+                .resize(max_width, mark_height, FilterType::Lanczos3)
+                .to_rgba8()
+        } else {
+            let font = self.font.as_ref()?;
+            let signature = Templates::global().watermark().render(&Vars::from([("author", author.to_string())]));
+            let signature = signature.trim();
+
+            if signature.is_empty() {
+                return None;
+            }
+
+            let mark = render_text(font, signature, mark_height as f32);
+
+            if mark.width() > max_width {
+                render_text(font, signature, mark_height as f32 * max_width as f32 / mark.width() as f32)
+            } else {
+                mark
+            }
+        };
+
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * self.opacity).round() as u8;
+        }
+
+        Some(mark)
+    }
+}
+
+/// White text with a dark shadow, so the signature is readable on any background
+fn render_text(font: &FontArc, text: &str, px: f32) -> RgbaImage {
+    let font = font.as_scaled(PxScale::from(px));
+    let shadow = (px / 16.0).ceil() as u32;
+    let mut glyphs: Vec<Glyph> = Vec::new();
+    let mut caret = 0.0;
+
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+
+        if let Some(previous) = glyphs.last() {
+            caret += font.kern(previous.id, id);
+        }
+
+        glyphs.push(id.with_scale_and_position(font.scale(), point(caret, font.ascent())));
+        caret += font.h_advance(id);
+    }
+
+    let mut mark = RgbaImage::new(caret.ceil() as u32 + shadow, font.height().ceil() as u32 + shadow);
+
+    for (offset, color) in [(shadow, [0, 0, 0]), (0, [255, 255, 255])] {
+        for glyph in &glyphs {
+            let Some(outlined) = font.outline_glyph(glyph.clone()) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+
+            outlined.draw(|x, y, coverage| {
+                let x = bounds.min.x as i64 + x as i64 + offset as i64;
+                let y = bounds.min.y as i64 + y as i64 + offset as i64;
+
+                if x >= 0 && y >= 0 && (x as u32) < mark.width() && (y as u32) < mark.height() {
+                    blend(mark.get_pixel_mut(x as u32, y as u32), color, coverage);
+                }
+            });
+        }
+    }
+
+    mark
+}
+
+/// Draws the color over the pixel with the given coverage
+fn blend(pixel: &mut Rgba<u8>, color: [u8; 3], coverage: f32) {
+    let alpha = coverage.clamp(0.0, 1.0);
+    let below = pixel[3] as f32 / 255.0 * (1.0 - alpha);
+    let total = alpha + below;
+
+    if total <= 0.0 {
+        return;
+    }
+
+    for (channel, value) in pixel.0.iter_mut().zip(color) {
+        *channel = ((value as f32 * alpha + *channel as f32 * below) / total).round() as u8;
+    }
+
+    pixel[3] = (total * 255.0).round() as u8;
+}
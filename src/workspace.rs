@@ -0,0 +1,126 @@
+use envconfig::Envconfig;
+use once_cell::sync::OnceCell;
+use std::{io, path::PathBuf};
+use uuid::Uuid;
+
+pub static INSTANCE: OnceCell<Workspace> = OnceCell::new();
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct WorkspaceConfig {
+    #[envconfig(from = "WORKSPACE_ROOT", default = "/tmp/beautiful_inno_bot")]
+    pub root: String,
+}
+
+/// Root for working directories of photo jobs. It belongs to a single bot process,
+/// so job directories found there on startup have been left by a crash
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(config: &WorkspaceConfig) -> Self {
+        let root = PathBuf::from(&config.root);
+
+        std::fs::create_dir_all(&root).expect("Can't create workspace root");
+
+        Self { root }
+    }
+
+    pub fn global() -> &'static Workspace {
+        INSTANCE.get().expect("Workspace is not initialized")
+    }
+
+    /// Creates an empty directory which is removed with all its files when the guard is dropped
+    pub fn job(&self) -> io::Result<JobDir> {
+        let path = self.root.join(Uuid::new_v4().to_string());
+
+        std::fs::create_dir(&path)?;
+
+        Ok(JobDir { path })
+    }
+
+    /// Removes job directories orphaned by crashes, must run before any job is started.
+    /// Only directories named like jobs are touched, so the root can be shared with other files.
+    /// Returns how many directories have been removed
+    pub fn sweep(&self) -> usize {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("Can't read workspace root {}: {e}", self.root.to_string_lossy());
+
+                return 0;
+            }
+        };
+
+        let mut removed = 0;
+
+        for entry in entries.flatten() {
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            let is_job = entry.file_name().to_str().is_some_and(|name| Uuid::parse_str(name).is_ok());
+
+            if !is_dir || !is_job {
+                continue;
+            }
+
+            let path = entry.path();
+
+            match std::fs::remove_dir_all(&path) {
+                Ok(_) => removed += 1,
+                Err(e) => warn!("Can't remove {}: {e}", path.to_string_lossy()),
+            }
+        }
+
+        removed
+    }
+}
+
+/// Working directory of a single job
+#[derive(Debug)]
+pub struct JobDir {
+    path: PathBuf,
+}
+
+impl JobDir {
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Can't remove job directory {}: {e}", self.path.to_string_lossy());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_removes_only_job_directories() {
+        let root = std::env::temp_dir().join(format!("workspace_test_{}", Uuid::new_v4()));
+        let workspace = Workspace::new(&WorkspaceConfig {
+            root: root.to_string_lossy().to_string(),
+        });
+        let orphan = root.join(Uuid::new_v4().to_string());
+
+        std::fs::create_dir(&orphan).unwrap();
+        std::fs::write(orphan.join("photo.jpg"), b"data").unwrap();
+        std::fs::write(root.join(Uuid::new_v4().to_string()), b"file").unwrap();
+        std::fs::write(root.join("foreign.txt"), b"file").unwrap();
+        std::fs::create_dir(root.join("foreign")).unwrap();
+
+        let removed = workspace.sweep();
+        let left = std::fs::read_dir(&root).unwrap().count();
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(!orphan.exists());
+        assert_eq!(left, 3);
+    }
+}